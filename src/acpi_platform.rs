@@ -9,26 +9,15 @@
 //! - Available Platform Profiles:
 //!  - https://mjmwired.net/kernel/Documentation/ABI/testing/sysfs-platform_profile
 
-use std::{fs, path::Path};
+use crate::{plan::Plan, sysroot};
 
 const SYSFS_PATH: &str = "/sys/firmware/acpi/platform_profile";
 
-pub fn supported() -> bool { Path::new(SYSFS_PATH).exists() }
+pub fn supported() -> bool { sysroot::path(SYSFS_PATH).exists() }
 
-pub fn battery() {
-    if let Err(why) = fs::write(SYSFS_PATH, "low-power") {
-        eprintln!("ACPI Platform Profile: could not set to low-power: {}", why);
-    }
-}
-
-pub fn balanced() {
-    if let Err(why) = fs::write(SYSFS_PATH, "balanced") {
-        eprintln!("ACPI Platform Profile: could not set to balanced: {}", why);
-    }
-}
-
-pub fn performance() {
-    if let Err(why) = fs::write(SYSFS_PATH, "performance") {
-        eprintln!("ACPI Platform Profile: could not set to performance: {}", why);
+/// Selects a platform profile, such as `low-power`, `balanced` or `performance`.
+pub fn set(plan: &mut Plan, profile: &str) {
+    if let Err(why) = plan.write(sysroot::path(SYSFS_PATH), profile) {
+        log::warn!("ACPI Platform Profile: could not set to {}: {}", profile, why);
     }
 }
@@ -68,14 +68,113 @@ pub enum Args {
         about = "Query or set the power profile",
         long_about = "Queries or sets the power profile.\n\n - If an argument is not provided, \
                       the power profile will be queried\n - Otherwise, that profile will be set, \
-                      if it is a valid profile"
+                      if it is a valid profile\n\nBesides the built-in battery, balanced and \
+                      performance profiles, any profile defined in /etc/system76-power/profiles \
+                      may be used."
     )]
     Profile {
+        #[clap(help = "set the power profile")]
+        profile:  Option<String>,
         #[clap(
-            help = "set the power profile",
-            possible_values = &["battery", "balanced", "performance"],
+            long = "list",
+            help = "List the available profiles",
+            conflicts_with_all = &["profile", "describe", "dry-run", "create", "startup"]
         )]
-        profile: Option<String>,
+        list:     bool,
+        #[clap(
+            long = "describe",
+            help = "Show the definition of the profile, or of the active profile",
+            conflicts_with_all = &["create", "startup"]
+        )]
+        describe: bool,
+        #[clap(
+            long = "dry-run",
+            help = "Show the changes setting the profile would make, without making them",
+            requires = "profile",
+            conflicts_with_all = &["describe", "create", "startup"]
+        )]
+        dry_run:  bool,
+        #[clap(
+            long = "create",
+            help = "Create or replace the profile from a JSON definition",
+            value_name = "FILE",
+            requires = "profile",
+            conflicts_with = "startup"
+        )]
+        create:   Option<String>,
+        #[clap(
+            long = "startup",
+            help = "Set the profile applied at boot: 'restore' for the last profile, or a profile \
+                    name",
+            value_name = "PROFILE",
+            conflicts_with = "profile"
+        )]
+        startup:  Option<String>,
+        #[clap(
+            short = 'v',
+            long = "verbose",
+            help = "Show the result of each change made when the profile was last applied",
+            conflicts_with_all = &["list", "describe", "dry-run", "create", "startup"]
+        )]
+        verbose:  bool,
+    },
+    #[clap(
+        about = "List the profiles held by applications",
+        long_about = "Lists the holds which applications have on profiles through the HoldProfile \
+                      method. While any are held, the battery profile takes precedence over \
+                      performance, and the profile that was active before is applied once they \
+                      are all released. Setting a profile releases every hold."
+    )]
+    Holds,
+    #[clap(
+        about = "Restore the values tunables had before the daemon changed them",
+        long_about = "Restores the values that kernel tunables had before the daemon first \
+                      changed them, as is done when the daemon stops. The next profile that is \
+                      set is applied again."
+    )]
+    RestoreDefaults,
+    #[clap(
+        about = "Query or set automatic profile switching",
+        long_about = "Queries or sets whether the daemon switches profiles when the AC adapter is \
+                      plugged in or unplugged.\n\nThe profiles used are configured in \
+                      /etc/system76-power/config.json."
+    )]
+    AutoSwitch {
+        #[clap(
+            help = "Set whether automatic profile switching should be on or off",
+            possible_values = &["off", "on"],
+        )]
+        state: Option<String>,
+    },
+    #[clap(
+        about = "Show the PCIe ASPM policy and link states",
+        long_about = "Shows the PCIe Active State Power Management policy, and the link states \
+                      that each device ended up with after the profile was applied."
+    )]
+    Aspm,
+    #[clap(
+        about = "Query or set the CPU power limits",
+        long_about = "Shows the PL1, PL2 and PL4 power limits and the TCC offset that the active \
+                      profile configures, the ones in effect, and the range within which they may \
+                      be customized on this model, followed by the constraints of every powercap \
+                      zone.\n\nCustom limits take the place of those of every profile until they \
+                      are reset, and setting them requires authorization."
+    )]
+    Limits {
+        #[clap(long = "pl1", help = "Set a custom PL1 in watts", value_name = "WATTS")]
+        pl1:        Option<u8>,
+        #[clap(long = "pl2", help = "Set a custom PL2 in watts", value_name = "WATTS")]
+        pl2:        Option<u8>,
+        #[clap(long = "pl4", help = "Set a custom PL4 in watts", value_name = "WATTS")]
+        pl4:        Option<u8>,
+        #[clap(
+            long = "tcc-offset",
+            help = "Set a custom TCC offset in degrees Celsius below TjMax",
+            value_name = "DEGREES"
+        )]
+        tcc_offset: Option<u8>,
+        #[clap(long = "reset", help = "Go back to the limits of the profile")]
+        reset:      bool,
     },
     Graphics {
         #[clap(subcommand)]
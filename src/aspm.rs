@@ -0,0 +1,172 @@
+// Copyright 2022 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! PCIe Active State Power Management, through the global policy of the `pcie_aspm` module and
+//! the link states of each device.
+
+use crate::{
+    errors::AspmError,
+    kernel_parameters::{KernelParameter, PcieAspm},
+    plan::Plan,
+    sysroot,
+    util::{alternatives, selected},
+};
+use std::{collections::BTreeMap, fs, io, path::Path};
+use sysfs_class::{PciDevice, SysClass};
+
+/// The link states found in the `link` directory of a device. Which ones exist depends on the
+/// capabilities of the device and of its link.
+pub const LINK_STATES: &[&str] =
+    &["clkpm", "l0s_aspm", "l1_aspm", "l1_1_aspm", "l1_2_aspm", "l1_1_pcipm", "l1_2_pcipm"];
+
+/// A `(device, link state, enabled)` entry.
+pub type LinkState = (String, String, bool);
+
+/// The active policy, which is marked out of the supported ones, as in
+/// `default performance [powersave] powersupersave`.
+pub fn policy() -> Option<String> {
+    let policies = fs::read_to_string(PcieAspm::default().get_path()).ok()?;
+    selected(&policies).map(String::from)
+}
+
+/// Sets the policy, which is one of `default`, `performance`, `powersave` or `powersupersave`.
+/// Kernels older than 5.5 lack `powersupersave`, and `powersave` is set instead.
+pub fn set_policy(plan: &mut Plan, policy: &str) -> Result<(), AspmError> {
+    let parameter = PcieAspm::default();
+    let path = parameter.get_path();
+
+    // ASPM is not built into the kernel.
+    let offered = match fs::read_to_string(&path) {
+        Ok(offered) => offered,
+        Err(_) => return Ok(()),
+    };
+
+    let is_offered = |policy| alternatives(&offered).any(|offered| offered == policy);
+    let policy = match policy {
+        _ if is_offered(policy) => policy,
+        "powersupersave" if is_offered("powersave") => {
+            log::info!("ASPM policy powersupersave is not supported, setting powersave");
+            "powersave"
+        }
+        _ => return Err(AspmError::Unsupported(policy.to_owned())),
+    };
+
+    match plan.write(path, policy) {
+        // The firmware has not granted control of ASPM to the OS.
+        Err(why) if why.kind() == io::ErrorKind::PermissionDenied => {
+            log::info!("ASPM policy is controlled by the firmware");
+            Ok(())
+        }
+        result => result.map_err(|why| AspmError::Policy(policy.to_owned(), why)),
+    }
+}
+
+/// Sets the link states of every device which has them. The states in `devices` are keyed by
+/// the address of the device, and take precedence over the ones in `defaults`.
+pub fn set_link_states(
+    plan: &mut Plan,
+    defaults: &BTreeMap<String, bool>,
+    devices: &BTreeMap<String, BTreeMap<String, bool>>,
+) -> Result<(), AspmError> {
+    let unknown = defaults
+        .keys()
+        .chain(devices.values().flat_map(BTreeMap::keys))
+        .find(|state| !LINK_STATES.contains(&state.as_str()));
+    if let Some(state) = unknown {
+        return Err(AspmError::UnknownLinkState(state.clone()));
+    }
+
+    let mut result = Ok(());
+    for device in sysroot::class_iter::<PciDevice>() {
+        let device = match device {
+            Ok(device) => device,
+            Err(why) => {
+                log::warn!("failed to iterate PCI device: {}", why);
+                continue;
+            }
+        };
+
+        let mut states = defaults.clone();
+        if let Some(overrides) = devices.get(device.id()) {
+            states.extend(overrides.iter().map(|(state, &enabled)| (state.clone(), enabled)));
+        }
+
+        for (state, enabled) in states {
+            let path = device.path().join("link").join(&state);
+            if !path.exists() {
+                continue;
+            }
+
+            if let Err(why) = plan.write(&path, if enabled { "1" } else { "0" }) {
+                result = Err(AspmError::LinkState { device: device.id().to_owned(), state, why });
+            }
+        }
+    }
+
+    result
+}
+
+/// The link states of each device which has them.
+pub fn link_states() -> Vec<LinkState> {
+    let mut states = Vec::new();
+    for device in sysroot::class_iter::<PciDevice>().filter_map(Result::ok) {
+        let link = device.path().join("link");
+        for &state in LINK_STATES {
+            if let Some(enabled) = read_state(&link.join(state)) {
+                states.push((device.id().to_owned(), state.to_owned(), enabled));
+            }
+        }
+    }
+
+    states.sort();
+    states
+}
+
+fn read_state(path: &Path) -> Option<bool> {
+    let value = fs::read_to_string(path).ok()?;
+    Some(value.trim() == "1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysroot::FakeRoot;
+
+    #[test]
+    fn device_link_states_override_defaults() {
+        let root = FakeRoot::new("aspm");
+        root.write("/sys/bus/pci/devices/0000:00:1c.0/link/l1_aspm", "0\n");
+        root.write("/sys/bus/pci/devices/0000:00:1c.0/link/l0s_aspm", "0\n");
+        root.write("/sys/bus/pci/devices/0000:02:00.0/link/l1_aspm", "1\n");
+        root.write("/sys/module/pcie_aspm/parameters/policy", "[default] performance powersave\n");
+        assert_eq!(policy().as_deref(), Some("default"));
+
+        let states = |state: &str, enabled| vec![(state.to_owned(), enabled)].into_iter().collect();
+        let defaults: BTreeMap<String, bool> = states("l1_aspm", true);
+        let mut devices = BTreeMap::new();
+        devices.insert("0000:02:00.0".to_owned(), states("l1_aspm", false));
+
+        // The kernel does not offer powersupersave, so powersave is set instead.
+        let mut plan = Plan::new();
+        set_policy(&mut plan, "powersupersave").unwrap();
+        set_link_states(&mut plan, &defaults, &devices).unwrap();
+
+        assert_eq!(root.read("/sys/module/pcie_aspm/parameters/policy"), "powersave");
+        assert!(matches!(set_policy(&mut plan, "lowest"), Err(AspmError::Unsupported(_))));
+        assert_eq!(
+            link_states(),
+            vec![
+                ("0000:00:1c.0".to_owned(), "l0s_aspm".to_owned(), false),
+                ("0000:00:1c.0".to_owned(), "l1_aspm".to_owned(), true),
+                ("0000:02:00.0".to_owned(), "l1_aspm".to_owned(), false),
+            ]
+        );
+
+        devices.get_mut("0000:02:00.0").unwrap().insert("l3_aspm".into(), false);
+        assert!(matches!(
+            set_link_states(&mut plan, &defaults, &devices),
+            Err(AspmError::UnknownLinkState(_))
+        ));
+    }
+}
@@ -6,9 +6,9 @@ use dbus::{
     arg::{cast, Append, Arg, ArgType, Get, Iter, IterAppend, RefArg, Variant},
     strings::Signature,
 };
-use std::{collections::HashMap, fs, path::Path};
+use std::{collections::HashMap, fs};
 
-use crate::err_str;
+use crate::{err_str, sysroot};
 
 const START_THRESHOLD: &str = "/sys/class/power_supply/BAT0/charge_control_start_threshold";
 const END_THRESHOLD: &str = "/sys/class/power_supply/BAT0/charge_control_end_threshold";
@@ -69,11 +69,11 @@ impl<'a> Get<'a> for ChargeProfile {
 
 fn is_s76_ec() -> bool {
     // For now, only support thresholds on System76 hardware
-    Path::new("/sys/bus/acpi/devices/17761776:00").is_dir()
+    sysroot::path("/sys/bus/acpi/devices/17761776:00").is_dir()
 }
 
 fn supports_thresholds() -> bool {
-    Path::new(START_THRESHOLD).exists() && Path::new(END_THRESHOLD).exists()
+    sysroot::path(START_THRESHOLD).exists() && sysroot::path(END_THRESHOLD).exists()
 }
 
 pub fn get_charge_profiles() -> Vec<ChargeProfile> {
@@ -115,8 +115,8 @@ pub(crate) fn get_charge_thresholds() -> Result<(u8, u8), String> {
         return Err(UNSUPPORTED_ERROR.to_string());
     }
 
-    let start_str = fs::read_to_string(START_THRESHOLD).map_err(err_str)?;
-    let end_str = fs::read_to_string(END_THRESHOLD).map_err(err_str)?;
+    let start_str = fs::read_to_string(sysroot::path(START_THRESHOLD)).map_err(err_str)?;
+    let end_str = fs::read_to_string(sysroot::path(END_THRESHOLD)).map_err(err_str)?;
 
     let start = start_str.trim().parse::<u8>().map_err(err_str)?;
     let end = end_str.trim().parse::<u8>().map_err(err_str)?;
@@ -135,10 +135,31 @@ pub(crate) fn set_charge_thresholds((start, end): (u8, u8)) -> Result<(), String
 
     // Without this, setting start threshold may fail if the previous end
     // threshold is higher.
-    fs::write(END_THRESHOLD, "100").map_err(err_str)?;
+    fs::write(sysroot::path(END_THRESHOLD), "100").map_err(err_str)?;
 
-    fs::write(START_THRESHOLD, format!("{}", start)).map_err(err_str)?;
-    fs::write(END_THRESHOLD, format!("{}", end)).map_err(err_str)?;
+    fs::write(sysroot::path(START_THRESHOLD), format!("{}", start)).map_err(err_str)?;
+    fs::write(sysroot::path(END_THRESHOLD), format!("{}", end)).map_err(err_str)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysroot::FakeRoot;
+
+    #[test]
+    fn thresholds_round_trip() {
+        let root = FakeRoot::new("charge-thresholds");
+        assert_eq!(get_charge_thresholds(), Err(UNSUPPORTED_ERROR.to_string()));
+
+        root.write("/sys/bus/acpi/devices/17761776:00/status", "15\n");
+        root.write(START_THRESHOLD, "90\n");
+        root.write(END_THRESHOLD, "100\n");
+        assert_eq!(get_charge_thresholds(), Ok((90, 100)));
+
+        assert_eq!(set_charge_thresholds((60, 50)), Err(ORDER_ERROR.to_string()));
+        assert_eq!(set_charge_thresholds((50, 60)), Ok(()));
+        assert_eq!(get_charge_thresholds(), Ok((50, 60)));
+    }
+}
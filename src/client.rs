@@ -4,16 +4,22 @@
 
 use crate::{
     args::{Args, GraphicsArgs},
+    aspm::LinkState,
     charge_thresholds::ChargeProfile,
-    err_str, Power, DBUS_IFACE, DBUS_NAME, DBUS_PATH,
+    cpufreq::{self, CoreType},
+    cpus, err_str,
+    hardware::LimitEntry,
+    plan::ReportEntry,
+    powercap::ConstraintEntry,
+    HoldEntry, Power, DBUS_IFACE, DBUS_NAME, DBUS_PATH,
 };
 use dbus::{
-    arg::Append,
+    arg::{Append, AppendAll},
     blocking::{BlockingSender, Connection},
     Message,
 };
 use intel_pstate::PState;
-use std::{io, time::Duration};
+use std::{fs, io, time::Duration};
 use sysfs_class::{Backlight, Brightness, Leds, SysClass};
 
 static TIMEOUT: u64 = 60 * 1000;
@@ -32,10 +38,18 @@ impl PowerClient {
         &mut self,
         method: &str,
         append: Option<A>,
+    ) -> Result<Message, String> {
+        self.call_method_with(method, append.map(|arg| (arg,)))
+    }
+
+    fn call_method_with<A: AppendAll>(
+        &mut self,
+        method: &str,
+        args: Option<A>,
     ) -> Result<Message, String> {
         let mut m = Message::new_method_call(DBUS_NAME, DBUS_PATH, DBUS_IFACE, method)?;
-        if let Some(arg) = append {
-            m = m.append1(arg);
+        if let Some(args) = args {
+            m.append_all(args);
         }
 
         let r = self.bus.send_with_reply_and_block(m, Duration::from_millis(TIMEOUT)).map_err(
@@ -49,20 +63,14 @@ impl PowerClient {
 
         Ok(r)
     }
-
-    fn set_profile(&mut self, profile: &str) -> Result<(), String> {
-        println!("setting power profile to {}", profile);
-        self.call_method::<bool>(profile, None)?;
-        Ok(())
-    }
 }
 
 impl Power for PowerClient {
-    fn performance(&mut self) -> Result<(), String> { self.set_profile("Performance") }
+    fn performance(&mut self) -> Result<(), String> { self.set_profile("performance") }
 
-    fn balanced(&mut self) -> Result<(), String> { self.set_profile("Balanced") }
+    fn balanced(&mut self) -> Result<(), String> { self.set_profile("balanced") }
 
-    fn battery(&mut self) -> Result<(), String> { self.set_profile("Battery") }
+    fn battery(&mut self) -> Result<(), String> { self.set_profile("battery") }
 
     fn get_external_displays_require_dgpu(&mut self) -> Result<bool, String> {
         let r = self.call_method::<bool>("GetExternalDisplaysRequireDGPU", None)?;
@@ -84,6 +92,116 @@ impl Power for PowerClient {
         r.get1().ok_or_else(|| "return value not found".to_string())
     }
 
+    fn set_profile(&mut self, name: &str) -> Result<(), String> {
+        println!("setting power profile to {}", name);
+        self.call_method::<&str>("SetProfile", Some(name)).map(|_| ())
+    }
+
+    fn get_profiles(&mut self) -> Result<Vec<String>, String> {
+        let r = self.call_method::<bool>("GetProfiles", None)?;
+        r.get1().ok_or_else(|| "return value not found".to_string())
+    }
+
+    fn hold_profile(
+        &mut self,
+        profile: &str,
+        reason: &str,
+        application_id: &str,
+    ) -> Result<u32, String> {
+        let r = self.call_method_with("HoldProfile", Some((profile, reason, application_id)))?;
+        r.get1().ok_or_else(|| "return value not found".to_string())
+    }
+
+    fn release_profile(&mut self, cookie: u32) -> Result<(), String> {
+        self.call_method::<u32>("ReleaseProfile", Some(cookie)).map(|_| ())
+    }
+
+    fn get_holds(&mut self) -> Result<Vec<HoldEntry>, String> {
+        let r = self.call_method::<bool>("GetHolds", None)?;
+        r.get1().ok_or_else(|| "return value not found".to_string())
+    }
+
+    fn describe_profile(&mut self, name: &str) -> Result<String, String> {
+        let r = self.call_method::<&str>("DescribeProfile", Some(name))?;
+        r.get1().ok_or_else(|| "return value not found".to_string())
+    }
+
+    fn plan_profile(
+        &mut self,
+        name: &str,
+    ) -> Result<Vec<(String, String, String, String)>, String> {
+        let r = self.call_method::<&str>("PlanProfile", Some(name))?;
+        r.get1().ok_or_else(|| "return value not found".to_string())
+    }
+
+    fn create_profile(&mut self, name: &str, definition: &str) -> Result<(), String> {
+        println!("creating power profile {}", name);
+        self.call_method_with("CreateProfile", Some((name, definition))).map(|_| ())
+    }
+
+    fn get_startup_profile(&mut self) -> Result<String, String> {
+        let r = self.call_method::<bool>("GetStartupProfile", None)?;
+        r.get1().ok_or_else(|| "return value not found".to_string())
+    }
+
+    fn set_startup_profile(&mut self, startup: &str) -> Result<(), String> {
+        println!("setting startup power profile to {}", startup);
+        self.call_method::<&str>("SetStartupProfile", Some(startup)).map(|_| ())
+    }
+
+    fn get_last_apply_report(&mut self) -> Result<Vec<ReportEntry>, String> {
+        let r = self.call_method::<bool>("GetLastApplyReport", None)?;
+        r.get1().ok_or_else(|| "return value not found".to_string())
+    }
+
+    fn restore_defaults(&mut self) -> Result<(), String> {
+        println!("restoring the original values of tunables");
+        self.call_method::<bool>("RestoreDefaults", None).map(|_| ())
+    }
+
+    fn get_auto_switch(&mut self) -> Result<bool, String> {
+        let r = self.call_method::<bool>("GetAutoSwitch", None)?;
+        r.get1().ok_or_else(|| "return value not found".to_string())
+    }
+
+    fn set_auto_switch(&mut self, enabled: bool) -> Result<(), String> {
+        println!("turning automatic profile switching {}", if enabled { "on" } else { "off" });
+        self.call_method::<bool>("SetAutoSwitch", Some(enabled)).map(|_| ())
+    }
+
+    fn get_aspm(&mut self) -> Result<(String, Vec<LinkState>), String> {
+        let r = self.call_method::<bool>("GetAspm", None)?;
+        match r.get2() {
+            (Some(policy), Some(links)) => Ok((policy, links)),
+            _ => Err("return value not found".to_string()),
+        }
+    }
+
+    fn get_limits(&mut self) -> Result<Vec<LimitEntry>, String> {
+        let r = self.call_method::<bool>("GetLimits", None)?;
+        r.get1().ok_or_else(|| "return value not found".to_string())
+    }
+
+    fn set_limit(&mut self, limit: &str, value: u8) -> Result<(), String> {
+        println!("setting {} to {}", limit, value);
+        self.call_method_with("SetLimit", Some((limit, value))).map(|_| ())
+    }
+
+    fn reset_limits(&mut self) -> Result<(), String> {
+        println!("resetting power limits to those of the profile");
+        self.call_method::<bool>("ResetLimits", None).map(|_| ())
+    }
+
+    fn get_powercap(&mut self) -> Result<Vec<ConstraintEntry>, String> {
+        let r = self.call_method::<bool>("GetPowercap", None)?;
+        r.get1().ok_or_else(|| "return value not found".to_string())
+    }
+
+    fn get_thermald(&mut self) -> Result<String, String> {
+        let r = self.call_method::<bool>("GetThermald", None)?;
+        r.get1().ok_or_else(|| "return value not found".to_string())
+    }
+
     fn get_switchable(&mut self) -> Result<bool, String> {
         let r = self.call_method::<bool>("GetSwitchable", None)?;
         r.get1().ok_or_else(|| "return value not found".to_string())
@@ -133,6 +251,14 @@ fn profile(client: &mut PowerClient) -> io::Result<()> {
     let profile = profile.as_ref().map_or("?", |s| s.as_str());
     println!("Power Profile: {}", profile);
 
+    if let Ok(startup) = client.get_startup_profile() {
+        println!("Startup Profile: {}", startup);
+    }
+
+    if let Ok(thermald) = client.get_thermald() {
+        println!("Thermald: {}", thermald);
+    }
+
     if let Ok(values) = PState::new().and_then(|pstate| pstate.values()) {
         println!(
             "CPU: {}% - {}%, {}",
@@ -142,6 +268,48 @@ fn profile(client: &mut PowerClient) -> io::Result<()> {
         );
     }
 
+    if let Some(driver) = cpufreq::scaling_driver(0) {
+        let mut line = format!("CPU Frequency Driver: {}", driver);
+        if let Some(mode) = cpufreq::amd_pstate_mode() {
+            line.push_str(&format!(" ({} mode)", mode));
+        }
+        if let Some(prefcore) = cpufreq::amd_pstate_prefcore() {
+            line.push_str(if prefcore { ", preferred cores" } else { ", no preferred cores" });
+        }
+        println!("{}", line);
+    }
+
+    let core_types = cpufreq::core_types();
+    if !core_types.is_empty() {
+        let performance =
+            core_types.values().filter(|&&core_type| core_type == CoreType::Performance).count();
+        let efficiency = core_types.len() - performance;
+        println!("CPU Cores: {} performance, {} efficiency", performance, efficiency);
+    }
+
+    if let Some(control) = cpus::smt_control() {
+        println!("SMT: {}", control);
+    }
+
+    let offline = cpus::offline_cpus();
+    if !offline.is_empty() {
+        let offline: Vec<String> = offline.iter().map(ToString::to_string).collect();
+        println!("Offline CPUs: {}", offline.join(", "));
+    }
+
+    if let Some(boost) = cpufreq::boost() {
+        println!("Boost: {}", if boost { "on" } else { "off" });
+    }
+
+    if let Some(preference) = cpufreq::energy_performance_preference(0) {
+        let available = cpufreq::available_energy_performance_preferences(0);
+        println!("Energy Performance Preference: {} (of {})", preference, available.join(", "));
+    }
+
+    if let Some(bias) = cpufreq::energy_perf_bias(0) {
+        println!("Energy Performance Bias: {}", bias);
+    }
+
     for backlight in Backlight::iter() {
         let backlight = backlight?;
         let brightness = backlight.actual_brightness()?;
@@ -169,16 +337,161 @@ fn profile(client: &mut PowerClient) -> io::Result<()> {
     Ok(())
 }
 
+fn apply_report(client: &mut PowerClient) -> Result<(), String> {
+    println!("Last Apply Report:");
+    for (setting, target, requested, result, kind) in client.get_last_apply_report()? {
+        let target =
+            if requested.is_empty() { target } else { format!("{} = {}", target, requested) };
+        if kind.is_empty() {
+            println!("  {}: {}: {}", setting, target, result);
+        } else {
+            println!("  {}: {}: {} ({})", setting, target, result, kind);
+        }
+    }
+
+    Ok(())
+}
+
+fn limits(client: &mut PowerClient) -> Result<(), String> {
+    println!("{:<12}{:>12}{:>12}{:>12}", "Limit", "Configured", "Effective", "Allowed");
+    for (limit, configured, effective, min, max) in client.get_limits()? {
+        let unit = if limit == "tcc_offset" { "C" } else { "W" };
+        let show = |value: &str| {
+            if value.is_empty() {
+                "-".to_owned()
+            } else {
+                format!("{} {}", value, unit)
+            }
+        };
+        let allowed =
+            if min.is_empty() { "-".to_owned() } else { format!("{}-{} {}", min, max, unit) };
+        println!("{:<12}{:>12}{:>12}{:>12}", limit, show(&configured), show(&effective), allowed);
+    }
+
+    let constraints = client.get_powercap()?;
+    if !constraints.is_empty() {
+        println!("\nPowercap Zones:");
+    }
+
+    for (zone, name, constraint, power_limit_uw, time_window_us, max_power_uw) in constraints {
+        let mut line =
+            format!("  {} ({}): {} {} W", zone, name, constraint, power_limit_uw as f64 / 1e6);
+        if time_window_us != 0 {
+            line.push_str(&format!(" over {} s", time_window_us as f64 / 1e6));
+        }
+        if max_power_uw != 0 {
+            line.push_str(&format!(", max {} W", max_power_uw as f64 / 1e6));
+        }
+        println!("{}", line);
+    }
+
+    Ok(())
+}
+
 pub fn client(args: &Args) -> Result<(), String> {
     let mut client = PowerClient::new()?;
 
     match args {
-        Args::Profile { profile: name } => match name.as_deref() {
-            Some("balanced") => client.balanced(),
-            Some("battery") => client.battery(),
-            Some("performance") => client.performance(),
-            _ => profile(&mut client).map_err(err_str),
+        Args::Profile { profile: name, list, describe, dry_run, create, startup, verbose } => {
+            if let Some(startup) = startup {
+                client.set_startup_profile(startup)
+            } else if *dry_run {
+                // Only reachable with a name, which clap requires alongside --dry-run.
+                let name = name.as_deref().unwrap_or_default();
+                for (path, old, new, command) in client.plan_profile(name)? {
+                    if command.is_empty() {
+                        let old = if old.is_empty() { "?" } else { old.as_str() };
+                        println!("write {}: {} -> {}", path, old, new);
+                    } else {
+                        println!("run {}", command);
+                    }
+                }
+                Ok(())
+            } else if *list {
+                for name in client.get_profiles()? {
+                    println!("{}", name);
+                }
+                Ok(())
+            } else if *describe {
+                let name = match name {
+                    Some(name) => name.clone(),
+                    None => client.get_profile()?,
+                };
+                println!("{}", client.describe_profile(&name)?);
+                Ok(())
+            } else if let Some(path) = create {
+                // Only reachable with a name, which clap requires alongside --create.
+                let name = name.as_deref().unwrap_or_default();
+                let definition = fs::read_to_string(path)
+                    .map_err(|why| format!("failed to read {}: {}", path, why))?;
+                client.create_profile(name, &definition)
+            } else if let Some(name) = name {
+                let result = client.set_profile(name);
+                if *verbose {
+                    apply_report(&mut client)?;
+                }
+                result
+            } else {
+                profile(&mut client).map_err(err_str)?;
+                if *verbose {
+                    apply_report(&mut client)?;
+                }
+                Ok(())
+            }
+        }
+        Args::Holds => {
+            let holds = client.get_holds()?;
+            if holds.is_empty() {
+                println!("No profile is held");
+            }
+
+            for (cookie, profile, reason, application_id) in holds {
+                println!("{}: {} held by {} ({})", cookie, profile, application_id, reason);
+            }
+
+            Ok(())
+        }
+        Args::RestoreDefaults => client.restore_defaults(),
+        Args::AutoSwitch { state } => match state.as_deref() {
+            Some("off") => client.set_auto_switch(false),
+            Some("on") => client.set_auto_switch(true),
+            _ => {
+                println!("{}", if client.get_auto_switch()? { "on" } else { "off" });
+                Ok(())
+            }
         },
+        Args::Aspm => {
+            let (policy, links) = client.get_aspm()?;
+            println!("ASPM Policy: {}", if policy.is_empty() { "unsupported" } else { &policy });
+
+            let mut last_device = None;
+            for (device, state, enabled) in &links {
+                if last_device != Some(device) {
+                    print!("{}{}:", if last_device.is_some() { "\n" } else { "" }, device);
+                    last_device = Some(device);
+                }
+                print!(" {} {}", state, if *enabled { "on" } else { "off" });
+            }
+            if last_device.is_some() {
+                println!();
+            }
+
+            Ok(())
+        }
+        Args::Limits { pl1, pl2, pl4, tcc_offset, reset } => {
+            if *reset {
+                client.reset_limits()?;
+            }
+
+            let custom = [("pl1", pl1), ("pl2", pl2), ("pl4", pl4), ("tcc_offset", tcc_offset)];
+            for (limit, value) in custom.iter() {
+                if let Some(value) = value {
+                    client.set_limit(limit, *value)?;
+                }
+            }
+
+            limits(&mut client)
+        }
         Args::Graphics { cmd } => match cmd.as_ref() {
             Some(GraphicsArgs::Compute) => client.set_graphics("compute"),
             Some(GraphicsArgs::Hybrid) => client.set_graphics("hybrid"),
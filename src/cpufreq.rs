@@ -1,42 +1,252 @@
 // Copyright 2022 System76 <info@system76.com>
 // SPDX-License-Identifier: GPL-3.0-only
 
-use crate::{util::write_value, Profile};
+use crate::{plan::Plan, sysroot};
 use concat_in_place::strcat;
-use std::fs;
+use std::{collections::BTreeMap, fmt::Display, fs, path::PathBuf};
 
-pub fn set(profile: Profile, max_percent: u8) {
-    if let Some(driver) = scaling_driver(0) {
-        let governor = if "intel_pstate" == driver.as_str() {
-            match profile {
-                Profile::Battery | Profile::Balanced => "powersave",
-                Profile::Performance => "performance",
-            }
-        } else {
-            match profile {
-                Profile::Battery => "conservative",
-                Profile::Balanced => "schedutil",
-                Profile::Performance => "performance",
-            }
+const AMD_PSTATE_PATH: &str = "/sys/devices/system/cpu/amd_pstate";
+const BOOST_PATH: &str = "/sys/devices/system/cpu/cpufreq/boost";
+
+/// The kind of a core of a hybrid CPU.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CoreType {
+    Performance,
+    Efficiency,
+}
+
+/// The maximum frequency of each CPU, as a percentage of its own hardware maximum. The
+/// percentages of performance and efficiency cores take the place of `all` on hybrid CPUs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MaxPercent {
+    pub all:     u8,
+    pub p_cores: Option<u8>,
+    pub e_cores: Option<u8>,
+}
+
+impl MaxPercent {
+    fn of(&self, core_type: Option<CoreType>) -> u8 {
+        let percent = match core_type {
+            Some(CoreType::Performance) => self.p_cores,
+            Some(CoreType::Efficiency) => self.e_cores,
+            None => None,
         };
 
-        if let Some((cpus, (min, max))) =
-            num_cpus().zip(frequency_minimum().zip(frequency_maximum()))
-        {
-            let max = max * max_percent.min(100) as usize / 100;
-            eprintln!("setting {} with max {}", governor, max);
+        percent.unwrap_or(self.all).min(100)
+    }
+}
+
+/// Sets the governor and maximum frequency of every online CPU. The `pstate_governor` is used
+/// with the `intel_pstate` and `amd-pstate-epp` scaling drivers, which only have the
+/// `performance` and `powersave` governors, and `governor` with all others.
+pub fn set(plan: &mut Plan, pstate_governor: &str, governor: &str, max_percent: MaxPercent) {
+    if let Some(driver) = scaling_driver(0) {
+        let governor = if has_pstate_governors(&driver) { pstate_governor } else { governor };
+        let core_types = core_types();
+        log::debug!("setting {} with max {:?}", governor, max_percent);
 
-            for cpu in 0..=cpus {
-                set_frequency_minimum(cpu, min);
-                set_frequency_maximum(cpu, max);
-                set_governor(cpu, governor);
+        for cpu in online_cpus() {
+            // The range of each CPU is its own, as it differs between the cores of hybrid CPUs
+            // and between the preferred cores of AMD CPUs.
+            if let Some((min, max)) = frequency_limits(cpu) {
+                let max = max * max_percent.of(core_types.get(&cpu).copied()) as usize / 100;
+                set_frequency_minimum(plan, cpu, min);
+                set_frequency_maximum(plan, cpu, max);
             }
+            set_governor(plan, cpu, governor);
+        }
+    }
+}
+
+/// The online CPUs, as listed by `/sys/devices/system/cpu/online`, or else every possible CPU.
+pub fn online_cpus() -> Vec<usize> {
+    match fs::read_to_string(sysroot::path("/sys/devices/system/cpu/online")) {
+        Ok(list) => parse_cpu_list(&list),
+        Err(_) => (0..=num_cpus().unwrap_or(0)).collect(),
+    }
+}
+
+/// Parses a list of CPUs such as `0-3,8,10-11`.
+pub(crate) fn parse_cpu_list(list: &str) -> Vec<usize> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        let mut bounds = range.splitn(2, '-').map(|bound| bound.parse::<usize>());
+        match (bounds.next(), bounds.next()) {
+            (Some(Ok(first)), None) => cpus.push(first),
+            (Some(Ok(first)), Some(Ok(last))) => cpus.extend(first..=last),
+            _ => log::warn!("invalid CPU range '{}'", range),
+        }
+    }
+
+    cpus
+}
+
+/// The type of each core of a hybrid CPU, or nothing if its cores are all alike. Intel
+/// lists its performance and efficiency cores in `/sys/devices/cpu_core` and
+/// `/sys/devices/cpu_atom`. Otherwise, cores with less than the highest `cpu_capacity` are
+/// efficiency cores.
+pub fn core_types() -> BTreeMap<usize, CoreType> {
+    let mut types = BTreeMap::new();
+    let pmus = [("cpu_core", CoreType::Performance), ("cpu_atom", CoreType::Efficiency)];
+    for &(pmu, core_type) in &pmus {
+        if let Ok(list) = fs::read_to_string(sysroot::path(format!("/sys/devices/{}/cpus", pmu))) {
+            types.extend(parse_cpu_list(&list).into_iter().map(|cpu| (cpu, core_type)));
+        }
+    }
+
+    if !types.is_empty() {
+        return types;
+    }
+
+    let capacities: BTreeMap<usize, usize> = online_cpus()
+        .into_iter()
+        .filter_map(|cpu| {
+            let path = format!("/sys/devices/system/cpu/cpu{}/cpu_capacity", cpu);
+            let capacity = fs::read_to_string(sysroot::path(path)).ok()?;
+            Some((cpu, capacity.trim_end().parse().ok()?))
+        })
+        .collect();
+
+    let highest = capacities.values().copied().max().unwrap_or(0);
+    if capacities.values().all(|&capacity| capacity == highest) {
+        return types;
+    }
+
+    capacities
+        .into_iter()
+        .map(|(cpu, capacity)| {
+            let core_type =
+                if capacity == highest { CoreType::Performance } else { CoreType::Efficiency };
+            (cpu, core_type)
+        })
+        .collect()
+}
+
+/// Whether the scaling driver manages hardware P-states itself, leaving only the `performance`
+/// and `powersave` governors.
+pub fn has_pstate_governors(driver: &str) -> bool {
+    matches!(driver, "intel_pstate" | "amd-pstate-epp")
+}
+
+/// The mode of the `amd-pstate` driver: `active` for `amd-pstate-epp`, or `passive` or `guided`
+/// for `amd-pstate`. `None` if the driver is not in use.
+pub fn amd_pstate_mode() -> Option<String> {
+    let status = fs::read_to_string(sysroot::path(AMD_PSTATE_PATH).join("status")).ok()?;
+    Some(status.trim_end().to_owned()).filter(|status| status != "disable")
+}
+
+/// Whether `amd-pstate` schedules tasks on the preferred cores of the CPU first.
+pub fn amd_pstate_prefcore() -> Option<bool> {
+    let prefcore = fs::read_to_string(sysroot::path(AMD_PSTATE_PATH).join("prefcore")).ok()?;
+    Some(prefcore.trim_end() == "enabled")
+}
+
+/// Whether frequencies above the base frequency are allowed, or `None` if boost cannot be
+/// controlled. With `intel_pstate`, turbo is controlled through `no_turbo` instead.
+pub fn boost() -> Option<bool> {
+    let path = boost_paths().into_iter().next()?;
+    Some(fs::read_to_string(path).ok()?.trim_end() == "1")
+}
+
+/// Allows or forbids frequencies above the base frequency, through the global `boost` attribute
+/// of `acpi-cpufreq` and `amd-pstate`, or else the `boost` attribute of each policy.
+pub fn set_boost(plan: &mut Plan, enabled: bool) {
+    for path in boost_paths() {
+        write_value(plan, &path.to_string_lossy(), u8::from(enabled));
+    }
+}
+
+fn boost_paths() -> Vec<PathBuf> {
+    let global = sysroot::path(BOOST_PATH);
+    if global.exists() {
+        return vec![global];
+    }
+
+    online_cpus()
+        .into_iter()
+        .map(|cpu| sysroot::path(format!("/sys/devices/system/cpu/cpufreq/policy{}/boost", cpu)))
+        .filter(|path| path.exists())
+        .collect()
+}
+
+/// The minimum and maximum frequency of a CPU. With `amd-pstate`, the maximum is that of its
+/// highest performance level, which is higher on preferred cores.
+pub fn frequency_limits(core: usize) -> Option<(usize, usize)> {
+    let read = |attribute: &str| {
+        let mut sys_path = sys_path(core);
+        let string = fs::read_to_string(strcat!(&mut sys_path, attribute)).ok()?;
+        string.trim_end().parse::<usize>().ok()
+    };
+
+    let max = read("amd_pstate_max_freq").or_else(|| read("cpuinfo_max_freq"))?;
+    Some((read("cpuinfo_min_freq")?, max))
+}
+
+/// Sets the energy performance preference of every CPU to the first of `preferences` that the
+/// scaling driver supports, such as `balance_performance`. This is only available with HWP on
+/// `intel_pstate`, and with `amd-pstate` in active mode.
+pub fn set_energy_performance_preference(plan: &mut Plan, preferences: &[String]) {
+    let available = available_energy_performance_preferences(0);
+    if available.is_empty() || preferences.is_empty() {
+        return;
+    }
+
+    let preference = match preferences.iter().find(|preference| available.contains(preference)) {
+        Some(preference) => preference,
+        None => {
+            log::warn!(
+                "none of the energy performance preferences {:?} are available out of {:?}",
+                preferences,
+                available
+            );
+            return;
         }
+    };
+
+    for cpu in online_cpus() {
+        let mut sys_path = sys_path(cpu);
+        let path = strcat!(&mut sys_path, "energy_performance_preference");
+        write_value(plan, path, preference);
     }
 }
 
+/// Sets the energy performance bias of every CPU, from 0 for the highest performance to 15 for
+/// the most power saving.
+pub fn set_energy_perf_bias(plan: &mut Plan, bias: u8) {
+    for cpu in online_cpus() {
+        let path = energy_perf_bias_path(cpu);
+        if fs::metadata(&path).is_ok() {
+            write_value(plan, &path, bias.min(15));
+        }
+    }
+}
+
+/// The energy performance preferences that the scaling driver supports.
+pub fn available_energy_performance_preferences(core: usize) -> Vec<String> {
+    let mut sys_path = sys_path(core);
+    fs::read_to_string(strcat!(&mut sys_path, "energy_performance_available_preferences"))
+        .map(|string| string.split_whitespace().map(String::from).collect())
+        .unwrap_or_default()
+}
+
+pub fn energy_performance_preference(core: usize) -> Option<String> {
+    let mut sys_path = sys_path(core);
+    fs::read_to_string(strcat!(&mut sys_path, "energy_performance_preference"))
+        .map(|string| string.trim_end().to_owned())
+        .ok()
+}
+
+pub fn energy_perf_bias(core: usize) -> Option<u8> {
+    fs::read_to_string(energy_perf_bias_path(core)).ok()?.trim_end().parse::<u8>().ok()
+}
+
+fn energy_perf_bias_path(core: usize) -> String {
+    let path = sysroot::path(format!("/sys/devices/system/cpu/cpu{}/power/energy_perf_bias", core));
+    path.to_string_lossy().into_owned()
+}
+
 pub fn num_cpus() -> Option<usize> {
-    let info = fs::read_to_string("/sys/devices/system/cpu/possible").ok()?;
+    let info = fs::read_to_string(sysroot::path("/sys/devices/system/cpu/possible")).ok()?;
     info.split('-').nth(1)?.trim_end().parse::<usize>().ok()
 }
 
@@ -61,22 +271,104 @@ pub fn scaling_driver(core: usize) -> Option<String> {
         .ok()
 }
 
-pub fn set_frequency_maximum(core: usize, frequency: usize) {
+pub fn set_frequency_maximum(plan: &mut Plan, core: usize, frequency: usize) {
     let mut sys_path = sys_path(core);
     let path = strcat!(&mut sys_path, "scaling_max_freq");
-    write_value(path, frequency);
+    write_value(plan, path, frequency);
 }
 
-pub fn set_frequency_minimum(core: usize, frequency: usize) {
+pub fn set_frequency_minimum(plan: &mut Plan, core: usize, frequency: usize) {
     let mut sys_path = sys_path(core);
     let path = strcat!(&mut sys_path, "scaling_min_freq");
-    write_value(path, frequency);
+    write_value(plan, path, frequency);
 }
 
-pub fn set_governor(core: usize, governor: &str) {
+pub fn set_governor(plan: &mut Plan, core: usize, governor: &str) {
     let mut sys_path = sys_path(core);
     let path = strcat!(&mut sys_path, "scaling_governor");
-    write_value(path, governor);
+    write_value(plan, path, governor);
+}
+
+fn write_value<V: Display>(plan: &mut Plan, path: &str, value: V) {
+    if let Err(why) = plan.write(path, value) {
+        log::warn!("failed to set value in {}: {}", path, why);
+    }
 }
 
-fn sys_path(core: usize) -> String { format!("/sys/devices/system/cpu/cpu{}/cpufreq/", core) }
+fn sys_path(core: usize) -> String {
+    let path = sysroot::path(format!("/sys/devices/system/cpu/cpu{}/cpufreq", core));
+    [&path.to_string_lossy(), "/"].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysroot::FakeRoot;
+
+    #[test]
+    fn amd_pstate_limits_each_core() {
+        let root = FakeRoot::new("cpufreq");
+        root.write("/sys/devices/system/cpu/possible", "0-1\n");
+        root.write("/sys/devices/system/cpu/amd_pstate/status", "active\n");
+        root.write("/sys/devices/system/cpu/amd_pstate/prefcore", "enabled\n");
+        for (cpu, max) in [(0, "5100000\n"), (1, "4800000\n")].iter() {
+            let dir = format!("/sys/devices/system/cpu/cpu{}/cpufreq/", cpu);
+            root.write(&[&dir, "scaling_driver"].concat(), "amd-pstate-epp\n");
+            root.write(&[&dir, "cpuinfo_min_freq"].concat(), "400000\n");
+            root.write(&[&dir, "cpuinfo_max_freq"].concat(), "5100000\n");
+            root.write(&[&dir, "amd_pstate_max_freq"].concat(), max);
+            root.write(&format!("/sys/devices/system/cpu/cpufreq/policy{}/boost", cpu), "1\n");
+        }
+
+        let mut plan = Plan::new();
+        set(
+            &mut plan,
+            "powersave",
+            "schedutil",
+            MaxPercent { all: 50, p_cores: None, e_cores: None },
+        );
+        set_boost(&mut plan, false);
+
+        assert_eq!(amd_pstate_mode().as_deref(), Some("active"));
+        assert_eq!(amd_pstate_prefcore(), Some(true));
+        assert_eq!(boost(), Some(false));
+        for (cpu, max) in [(0, "2550000"), (1, "2400000")].iter() {
+            let dir = format!("/sys/devices/system/cpu/cpu{}/cpufreq/", cpu);
+            assert_eq!(root.read(&[&dir, "scaling_governor"].concat()), "powersave");
+            assert_eq!(root.read(&[&dir, "scaling_max_freq"].concat()), *max);
+        }
+    }
+
+    #[test]
+    fn hybrid_cores_are_capped_separately() {
+        let root = FakeRoot::new("hybrid");
+        root.write("/sys/devices/system/cpu/possible", "0-4\n");
+        root.write("/sys/devices/system/cpu/online", "0-1,3\n");
+        root.write("/sys/devices/cpu_core/cpus", "0-1\n");
+        root.write("/sys/devices/cpu_atom/cpus", "2-3\n");
+        for (cpu, max) in [(0, "4700000\n"), (1, "4700000\n"), (3, "3500000\n")].iter() {
+            let dir = format!("/sys/devices/system/cpu/cpu{}/cpufreq/", cpu);
+            root.write(&[&dir, "scaling_driver"].concat(), "intel_pstate\n");
+            root.write(&[&dir, "cpuinfo_min_freq"].concat(), "400000\n");
+            root.write(&[&dir, "cpuinfo_max_freq"].concat(), max);
+        }
+
+        assert_eq!(parse_cpu_list("0-2,5,7-8\n"), vec![0, 1, 2, 5, 7, 8]);
+        assert_eq!(online_cpus(), vec![0, 1, 3]);
+        assert_eq!(core_types().get(&3), Some(&CoreType::Efficiency));
+
+        let mut plan = Plan::new();
+        set(
+            &mut plan,
+            "powersave",
+            "schedutil",
+            MaxPercent { all: 100, p_cores: Some(50), e_cores: Some(20) },
+        );
+
+        assert!(plan.steps().iter().all(|step| step.failure.is_none()));
+        for (cpu, max) in [(0, "2350000"), (1, "2350000"), (3, "700000")].iter() {
+            let dir = format!("/sys/devices/system/cpu/cpu{}/cpufreq/", cpu);
+            assert_eq!(root.read(&[&dir, "scaling_max_freq"].concat()), *max);
+        }
+    }
+}
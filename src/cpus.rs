@@ -0,0 +1,51 @@
+// Copyright 2022 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Whether CPUs are online, through CPU hotplug and the SMT control of the kernel.
+
+use crate::{cpufreq::parse_cpu_list, errors::CpuError, plan::Plan, sysroot};
+use std::fs;
+
+pub const SMT_CONTROL_PATH: &str = "/sys/devices/system/cpu/smt/control";
+
+fn online_path(cpu: usize) -> String { format!("/sys/devices/system/cpu/cpu{}/online", cpu) }
+
+/// The state of SMT: `on`, `off`, `forceoff`, `notsupported` or `notimplemented`.
+pub fn smt_control() -> Option<String> {
+    fs::read_to_string(sysroot::path(SMT_CONTROL_PATH)).ok().map(|value| value.trim().to_owned())
+}
+
+/// Enables or disables SMT, which takes the sibling threads of every core offline.
+pub fn set_smt(plan: &mut Plan, enabled: bool) -> Result<(), CpuError> {
+    match smt_control() {
+        Some(ref control) if control == "on" || control == "off" => (),
+        control => return Err(CpuError::SmtControl(control.unwrap_or_else(|| "missing".into()))),
+    }
+
+    let path = sysroot::path(SMT_CONTROL_PATH);
+    plan.write(&path, if enabled { "on" } else { "off" }).map_err(|why| CpuError::Write(path, why))
+}
+
+/// Whether a CPU is online, or `None` if it cannot be taken offline, as is usual for CPU 0.
+pub fn is_online(cpu: usize) -> Option<bool> {
+    let value = fs::read_to_string(sysroot::path(online_path(cpu))).ok()?;
+    Some(value.trim() == "1")
+}
+
+/// Brings a CPU online, or takes it offline.
+pub fn set_online(plan: &mut Plan, cpu: usize, online: bool) -> Result<(), CpuError> {
+    if is_online(cpu).is_none() {
+        return Err(CpuError::NotHotpluggable(cpu));
+    }
+
+    let path = sysroot::path(online_path(cpu));
+    plan.write(&path, online as u8).map_err(|why| CpuError::Write(path, why))
+}
+
+/// The CPUs which are offline, whether by CPU hotplug or because SMT is disabled.
+pub fn offline_cpus() -> Vec<usize> {
+    fs::read_to_string(sysroot::path("/sys/devices/system/cpu/offline"))
+        .map(|list| parse_cpu_list(&list))
+        .unwrap_or_default()
+}
@@ -0,0 +1,325 @@
+// Copyright 2022 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Detects when processes which select a profile are running.
+//!
+//! Processes are followed through the proc connector of the kernel, which reports each exec and
+//! exit over netlink. Where it is not available, such as without `CAP_NET_ADMIN`, every process
+//! in `/proc` is scanned on each step of the daemon loop instead.
+
+use super::config::AppRule;
+use crate::sysroot;
+use std::{
+    collections::BTreeMap,
+    convert::TryInto,
+    fs, io, mem,
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+    path::Path,
+};
+
+const NETLINK_CONNECTOR: libc::c_int = 11;
+const NLMSG_HDRLEN: usize = 16;
+const NLMSG_DONE: u16 = 3;
+const CN_MSG_LEN: usize = 20;
+const CN_IDX_PROC: u32 = 1;
+const CN_VAL_PROC: u32 = 1;
+const PROC_CN_MCAST_LISTEN: u32 = 1;
+const PROC_EVENT_EXEC: u32 = 0x0000_0002;
+const PROC_EVENT_EXIT: u32 = 0x8000_0000;
+
+/// The argument with which Steam launches each game, through its `reaper` process.
+const STEAM_LAUNCH: &str = "SteamLaunch";
+
+/// Tracks the processes which match a rule between steps of the daemon loop.
+pub struct AppWatcher {
+    connector: Option<ProcConnector>,
+    /// The rule matched by each process, by PID.
+    matched:   BTreeMap<u32, usize>,
+}
+
+impl AppWatcher {
+    pub fn new(rules: &[AppRule]) -> Self {
+        let connector = match ProcConnector::new() {
+            Ok(connector) => Some(connector),
+            Err(why) => {
+                log::warn!("proc connector is not available, scanning /proc instead: {}", why);
+                None
+            }
+        };
+
+        let mut watcher = Self { connector, matched: BTreeMap::new() };
+        watcher.scan(rules);
+        watcher
+    }
+
+    /// Returns the profile of the first rule which a running process matches.
+    pub fn step<'a>(&mut self, rules: &'a [AppRule]) -> Option<&'a str> {
+        let events = self.connector.as_mut().map(ProcConnector::events);
+        match events {
+            Some(Ok(events)) => {
+                for event in events {
+                    match event {
+                        Event::Exec(pid) => match Process::read(pid) {
+                            Some(process) => self.update(pid, process.rule(rules)),
+                            None => self.update(pid, None),
+                        },
+                        Event::Exit(pid) => self.update(pid, None),
+                    }
+                }
+            }
+            // Events are lost when the socket overflows, so scan every process again.
+            Some(Err(why)) => {
+                log::warn!("failed to receive proc connector events: {}", why);
+                self.scan(rules);
+            }
+            None => self.scan(rules),
+        }
+
+        let rule = self.matched.values().min()?;
+        rules.get(*rule).map(|rule| rule.profile.as_str())
+    }
+
+    fn update(&mut self, pid: u32, rule: Option<usize>) {
+        match rule {
+            Some(rule) => {
+                if self.matched.insert(pid, rule).is_none() {
+                    log::info!("process {} matches rule {}", pid, rule);
+                }
+            }
+            None => {
+                self.matched.remove(&pid);
+            }
+        }
+    }
+
+    /// Matches every running process against the rules.
+    fn scan(&mut self, rules: &[AppRule]) {
+        self.matched.clear();
+        let entries = match fs::read_dir(sysroot::path("/proc")) {
+            Ok(entries) => entries,
+            Err(why) => {
+                log::warn!("failed to read /proc: {}", why);
+                return;
+            }
+        };
+
+        for entry in entries.filter_map(Result::ok) {
+            let pid = match entry.file_name().to_str().and_then(|name| name.parse().ok()) {
+                Some(pid) => pid,
+                None => continue,
+            };
+
+            if let Some(rule) = Process::read(pid).and_then(|process| process.rule(rules)) {
+                self.matched.insert(pid, rule);
+            }
+        }
+    }
+}
+
+/// The names by which a process may be matched.
+struct Process {
+    /// The name of the process, which the kernel truncates to 15 bytes.
+    comm: String,
+    /// The file name of its executable, if it can be read.
+    exe:  Option<String>,
+    args: Vec<String>,
+}
+
+impl Process {
+    fn read(pid: u32) -> Option<Self> {
+        let dir = sysroot::path(format!("/proc/{}", pid));
+        let comm = fs::read_to_string(dir.join("comm")).ok()?.trim_end().to_owned();
+        let exe = fs::read_link(dir.join("exe"))
+            .ok()
+            .and_then(|exe| Some(exe.file_name()?.to_string_lossy().into_owned()));
+        let args = fs::read(dir.join("cmdline"))
+            .map(|cmdline| {
+                cmdline
+                    .split(|&byte| byte == 0)
+                    .filter(|arg| !arg.is_empty())
+                    .map(|arg| String::from_utf8_lossy(arg).into_owned())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Some(Self { comm, exe, args })
+    }
+
+    fn is_named(&self, name: &str) -> bool {
+        let comm = name.get(..15).unwrap_or(name);
+        let argv0 = self.args.first().map(|arg| file_name(arg));
+        self.comm == comm || self.exe.as_deref() == Some(name) || argv0 == Some(name)
+    }
+
+    fn is_steam_game(&self) -> bool {
+        self.is_named("reaper") && self.args.iter().any(|arg| arg == STEAM_LAUNCH)
+    }
+
+    /// The index of the first rule which the process matches.
+    fn rule(&self, rules: &[AppRule]) -> Option<usize> {
+        rules.iter().position(|rule| {
+            rule.processes.iter().any(|name| self.is_named(name))
+                || (rule.steam_games && self.is_steam_game())
+        })
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    Path::new(path).file_name().and_then(|name| name.to_str()).unwrap_or(path)
+}
+
+enum Event {
+    Exec(u32),
+    Exit(u32),
+}
+
+/// A netlink socket which receives the process events of the proc connector.
+struct ProcConnector {
+    file: fs::File,
+}
+
+impl AsRawFd for ProcConnector {
+    fn as_raw_fd(&self) -> RawFd { self.file.as_raw_fd() }
+}
+
+impl ProcConnector {
+    fn new() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC,
+                NETLINK_CONNECTOR,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Owning the descriptor closes it, even if subscribing fails.
+        let file = unsafe { fs::File::from_raw_fd(fd) };
+
+        let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_groups = CN_IDX_PROC;
+        let result = unsafe {
+            libc::bind(
+                fd,
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut message = [0; NLMSG_HDRLEN + CN_MSG_LEN + 4];
+        let len = message.len() as u32;
+        message[0..4].copy_from_slice(&len.to_ne_bytes());
+        message[4..6].copy_from_slice(&NLMSG_DONE.to_ne_bytes());
+        let cn_msg = &mut message[NLMSG_HDRLEN..];
+        cn_msg[0..4].copy_from_slice(&CN_IDX_PROC.to_ne_bytes());
+        cn_msg[4..8].copy_from_slice(&CN_VAL_PROC.to_ne_bytes());
+        cn_msg[16..18].copy_from_slice(&4u16.to_ne_bytes());
+        cn_msg[CN_MSG_LEN..].copy_from_slice(&PROC_CN_MCAST_LISTEN.to_ne_bytes());
+
+        let sent =
+            unsafe { libc::send(fd, message.as_ptr() as *const libc::c_void, message.len(), 0) };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { file })
+    }
+
+    /// Receives the events which are queued, without waiting for more.
+    fn events(&mut self) -> io::Result<Vec<Event>> {
+        let mut events = Vec::new();
+        let mut buffer = vec![0u8; 4096];
+        loop {
+            let received = unsafe {
+                libc::recv(
+                    self.as_raw_fd(),
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                    0,
+                )
+            };
+            if received < 0 {
+                let why = io::Error::last_os_error();
+                return match why.kind() {
+                    io::ErrorKind::WouldBlock => Ok(events),
+                    _ => Err(why),
+                };
+            }
+
+            let mut messages = &buffer[..received as usize];
+            while messages.len() >= NLMSG_HDRLEN {
+                let len = u32::from_ne_bytes(messages[0..4].try_into().unwrap()) as usize;
+                if len < NLMSG_HDRLEN || len > messages.len() {
+                    break;
+                }
+
+                // The proc_event follows the cn_msg header: what, cpu, timestamp, then the PID
+                // and TGID of the process.
+                let event = &messages[NLMSG_HDRLEN..len];
+                if event.len() >= CN_MSG_LEN + 24 {
+                    let field = |offset: usize| {
+                        let offset = CN_MSG_LEN + offset;
+                        u32::from_ne_bytes(event[offset..offset + 4].try_into().unwrap())
+                    };
+                    let (what, pid, tgid) = (field(0), field(16), field(20));
+
+                    // Only the main thread of each process is of interest.
+                    if pid == tgid {
+                        match what {
+                            PROC_EVENT_EXEC => events.push(Event::Exec(pid)),
+                            PROC_EVENT_EXIT => events.push(Event::Exit(pid)),
+                            _ => (),
+                        }
+                    }
+                }
+
+                messages = &messages[((len + 3) & !3).min(messages.len())..];
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysroot::FakeRoot;
+
+    fn process(root: &FakeRoot, pid: u32, comm: &str, args: &[&str]) {
+        root.write(&format!("/proc/{}/comm", pid), &format!("{}\n", comm));
+        let cmdline: String = args.iter().map(|arg| [arg, "\0"].concat()).collect();
+        root.write(&format!("/proc/{}/cmdline", pid), &cmdline);
+    }
+
+    #[test]
+    fn rules_match_running_processes() {
+        let root = FakeRoot::new("apps");
+        process(&root, 1, "systemd", &["/sbin/init"]);
+        process(&root, 200, "reaper", &["/steam/reaper", STEAM_LAUNCH, "AppId=570", "--"]);
+
+        let rules = vec![
+            AppRule {
+                profile: "performance".into(),
+                processes: vec!["cargo".into()],
+                ..AppRule::default()
+            },
+            AppRule { profile: "balanced".into(), steam_games: true, ..AppRule::default() },
+        ];
+
+        let mut watcher = AppWatcher { connector: None, matched: BTreeMap::new() };
+        assert_eq!(watcher.step(&rules), Some("balanced"));
+
+        process(&root, 300, "cargo", &["/home/user/.cargo/bin/cargo", "build"]);
+        assert_eq!(watcher.step(&rules), Some("performance"));
+
+        fs::remove_dir_all(sysroot::path("/proc/300")).unwrap();
+        fs::remove_dir_all(sysroot::path("/proc/200")).unwrap();
+        assert_eq!(watcher.step(&rules), None);
+    }
+}
@@ -0,0 +1,53 @@
+// Copyright 2022 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Detects when the AC adapter is plugged in or unplugged.
+
+use crate::sysroot;
+use std::{fs, path::Path};
+
+const POWER_SUPPLY_PATH: &str = "/sys/class/power_supply";
+
+/// Tracks the AC state between steps of the daemon loop.
+pub struct AutoSwitch {
+    last: Option<bool>,
+}
+
+impl AutoSwitch {
+    pub fn new() -> Self { Self { last: ac_online() } }
+
+    /// Returns the new AC state if it changed since the previous step.
+    pub fn step(&mut self) -> Option<bool> {
+        let online = ac_online()?;
+        let changed = matches!(self.last, Some(last) if last != online);
+        self.last = Some(online);
+
+        if changed {
+            Some(online)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether any AC adapter is online, or `None` if the system has no AC adapter.
+pub fn ac_online() -> Option<bool> {
+    let mut online = None;
+
+    for entry in fs::read_dir(sysroot::path(POWER_SUPPLY_PATH)).ok()?.filter_map(Result::ok) {
+        let path = entry.path();
+        if read_trimmed(&path.join("type")).as_deref() != Some("Mains") {
+            continue;
+        }
+
+        let supply_online = read_trimmed(&path.join("online")).as_deref() == Some("1");
+        online = Some(online.unwrap_or(false) || supply_online);
+    }
+
+    online
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|value| value.trim().to_owned())
+}
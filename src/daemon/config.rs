@@ -0,0 +1,619 @@
+// Copyright 2022 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Declarative profile definitions.
+//!
+//! Each profile is described by a [`ProfileConfig`]. The built-in definitions match the values
+//! that system76-power has always applied, and may be overridden per site by placing a JSON file
+//! named after the profile in [`PROFILES_DIR`], such as
+//! `/etc/system76-power/profiles/battery.json`. Keys which are omitted from an override keep their
+//! built-in value. Files with any other name define additional profiles, which start from the
+//! built-in profile given by their `base` key.
+//!
+//! Settings of the daemon itself are read from [`CONFIG_FILE`] as a [`DaemonConfig`].
+
+use crate::{powercap::ConstraintLimit, sysroot, Profile};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+pub const CONFIG_FILE: &str = "/etc/system76-power/config.json";
+pub const PROFILES_DIR: &str = "/etc/system76-power/profiles";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("invalid profile name '{}'", _0)]
+    InvalidName(String),
+    #[error("profile '{}' does not exist", _0)]
+    NotFound(String),
+    #[error("failed to read {:?}: {}", _0, _1)]
+    Read(PathBuf, io::Error),
+    #[error("invalid definition in {:?}: {}", _0, _1)]
+    Parse(PathBuf, serde_json::Error),
+    #[error("failed to write {:?}: {}", _0, _1)]
+    Write(PathBuf, io::Error),
+}
+
+/// Settings of the daemon.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    pub autoswitch:     AutoSwitchConfig,
+    pub audio:          AudioOverrideConfig,
+    /// Profiles applied while certain applications run, of which the first rule that matches a
+    /// running process takes precedence.
+    pub apps:           Vec<AppRule>,
+    /// Whether the interface of power-profiles-daemon is exported as well, for desktops which
+    /// select profiles through it.
+    pub power_profiles: bool,
+}
+
+/// Profiles applied when the AC adapter is plugged in or unplugged.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AutoSwitchConfig {
+    pub enabled: bool,
+    pub ac:      String,
+    pub battery: String,
+}
+
+/// A profile applied while any process which matches the rule is running.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppRule {
+    pub profile:     String,
+    /// Names of executables, compared with the name of each process, the file name of its
+    /// executable and that of its first argument.
+    pub processes:   Vec<String>,
+    /// Whether games launched by Steam match.
+    pub steam_games: bool,
+}
+
+/// Audio settings which take precedence over those of every profile.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioOverrideConfig {
+    /// Whether profiles may enable audio power saving, which causes audible clicks on some
+    /// machines when the codec powers up.
+    pub power_save: bool,
+}
+
+impl Default for AudioOverrideConfig {
+    fn default() -> Self { Self { power_save: true } }
+}
+
+impl Default for AutoSwitchConfig {
+    fn default() -> Self {
+        Self { enabled: false, ac: "balanced".into(), battery: "battery".into() }
+    }
+}
+
+impl DaemonConfig {
+    /// Loads the daemon settings, using the defaults if the file is missing or invalid.
+    pub fn load() -> Self {
+        let path = sysroot::path(CONFIG_FILE);
+        let result = match fs::read_to_string(&path) {
+            Ok(contents) => {
+                serde_json::from_str(&contents).map_err(|why| ConfigError::Parse(path.clone(), why))
+            }
+            Err(why) if why.kind() == io::ErrorKind::NotFound => return Self::default(),
+            Err(why) => Err(ConfigError::Read(path, why)),
+        };
+
+        result.unwrap_or_else(|why| {
+            log::error!("{}; using default settings", why);
+            Self::default()
+        })
+    }
+}
+
+/// Settings applied by a power profile.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProfileConfig {
+    /// Built-in profile whose settings are used for keys that the definition omits.
+    pub base:                  Option<Profile>,
+    pub description:           String,
+    /// Value written to `/sys/firmware/acpi/platform_profile`. When the platform supports it,
+    /// this replaces every other setting.
+    pub acpi_platform_profile: String,
+    /// Maximum seconds of dirty data that may be lost on sudden power loss.
+    pub max_lost_work:         u32,
+    /// Value of `/proc/sys/vm/laptop_mode`.
+    pub laptop_mode:           u8,
+    pub radeon:                RadeonConfig,
+    /// SCSI / SATA link power management policies, the first supported one is used.
+    pub scsi_link_policies:    Vec<String>,
+    pub disks:                 DiskConfig,
+    pub audio:                 AudioConfig,
+    pub wifi:                  WifiConfig,
+    pub backlight:             BacklightConfig,
+    /// Whether PCI runtime power management is enabled, if supported.
+    pub pci_runtime_pm:        bool,
+    pub aspm:                  AspmConfig,
+    pub cpus:                  CpuConfig,
+    pub cpufreq:               CpuFreqConfig,
+    pub pstate:                PStateConfig,
+    /// Limits of powercap zones, keyed by zone and then by constraint, such as
+    /// `{ "package": { "long_term": { "power_limit_w": 45 } } }`.
+    pub powercap:              BTreeMap<String, BTreeMap<String, ConstraintLimit>>,
+    /// Which of the model-specific power limits to apply, if any. They are applied after
+    /// `powercap`, and take precedence over it.
+    pub model_limits:          Option<Profile>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RadeonConfig {
+    pub power_profile: String,
+    pub dpm_state:     String,
+    pub dpm_perf:      String,
+}
+
+/// Power management of rotational disks.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiskConfig {
+    /// Advanced Power Management level set with `hdparm -B`, from 1 to 254. Levels up to 127
+    /// allow the disk to spin down.
+    pub apm_level:            Option<u8>,
+    /// Runtime autosuspend delay in milliseconds, or -1 to disable autosuspend.
+    pub autosuspend_delay_ms: Option<i32>,
+}
+
+/// Power saving of HDA and AC'97 audio codecs.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioConfig {
+    /// Seconds of idle time after which the codec is powered down, or 0 to keep it powered.
+    pub power_save:            u32,
+    /// Whether the controller is powered down along with the codec.
+    pub power_save_controller: bool,
+}
+
+impl AudioConfig {
+    /// Keeps the codec and the controller powered.
+    pub fn disabled() -> Self { Self { power_save: 0, power_save_controller: false } }
+}
+
+/// Power saving of wireless interfaces.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WifiConfig {
+    /// Whether power saving is enabled, or `None` to leave it unchanged.
+    pub power_save:  Option<bool>,
+    /// Power level from 1 to 5, only used when power saving is enabled through the module
+    /// parameters of a driver which does not support nl80211 power save.
+    pub power_level: u8,
+}
+
+/// PCIe Active State Power Management.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AspmConfig {
+    /// Policy of the `pcie_aspm` module: `default`, `performance`, `powersave` or
+    /// `powersupersave`, or `None` to leave it unchanged.
+    pub policy:      Option<String>,
+    /// Link states, such as `l1_aspm`, set on every device which has them.
+    pub link_states: BTreeMap<String, bool>,
+    /// Link states of individual devices, keyed by PCI address such as `0000:02:00.0`, which
+    /// take precedence over `link_states`.
+    pub devices:     BTreeMap<String, BTreeMap<String, bool>>,
+}
+
+/// Brightness caps, in percent, applied to backlights which are brighter.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BacklightConfig {
+    pub screen:   Option<u64>,
+    pub keyboard: Option<u64>,
+}
+
+/// CPUs taken offline to save power. Those which a previous profile took offline are brought
+/// back online.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CpuConfig {
+    /// Whether SMT is disabled, which takes the sibling threads of every core offline.
+    pub disable_smt: bool,
+    /// CPUs taken offline, by their number.
+    pub offline:     Vec<usize>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CpuFreqConfig {
+    /// Governor used with the `intel_pstate` scaling driver.
+    pub pstate_governor:               String,
+    /// Governor used with every other scaling driver.
+    pub governor:                      String,
+    /// Maximum frequency, as a percentage of the hardware maximum of each CPU.
+    pub max_percent:                   u8,
+    /// Maximum frequency of the performance cores of a hybrid CPU, in place of `max_percent`.
+    pub p_core_max_percent:            Option<u8>,
+    /// Maximum frequency of the efficiency cores of a hybrid CPU, in place of `max_percent`.
+    pub e_core_max_percent:            Option<u8>,
+    /// Energy performance preferences, the first supported one is used.
+    pub energy_performance_preference: Vec<String>,
+    /// Energy performance bias, from 0 for the highest performance to 15 for the most power
+    /// saving, or `None` to leave it unchanged.
+    pub energy_perf_bias:              Option<u8>,
+    /// Whether frequencies above the base frequency are allowed with `acpi-cpufreq` and
+    /// `amd-pstate`, or `None` to leave it unchanged.
+    pub boost:                         Option<bool>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PStateConfig {
+    pub min_perf_pct:      u8,
+    pub max_perf_pct:      u8,
+    pub no_turbo:          bool,
+    pub hwp_dynamic_boost: Option<bool>,
+}
+
+impl Default for ProfileConfig {
+    fn default() -> Self { Self::balanced() }
+}
+
+impl Default for RadeonConfig {
+    fn default() -> Self { ProfileConfig::balanced().radeon }
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self { ProfileConfig::balanced().audio }
+}
+
+impl Default for WifiConfig {
+    fn default() -> Self { ProfileConfig::balanced().wifi }
+}
+
+impl Default for CpuFreqConfig {
+    fn default() -> Self { ProfileConfig::balanced().cpufreq }
+}
+
+impl Default for PStateConfig {
+    fn default() -> Self { ProfileConfig::balanced().pstate }
+}
+
+impl RadeonConfig {
+    fn new(power_profile: &str, dpm_state: &str, dpm_perf: &str) -> Self {
+        Self {
+            power_profile: power_profile.into(),
+            dpm_state:     dpm_state.into(),
+            dpm_perf:      dpm_perf.into(),
+        }
+    }
+}
+
+impl ProfileConfig {
+    pub fn balanced() -> Self {
+        Self {
+            base:                  None,
+            description:           "Balances performance and power usage".into(),
+            acpi_platform_profile: "balanced".into(),
+            // The dirty kernel parameter controls how often the OS will sync data to disks. The
+            // less frequently this occurs, the more power can be saved, yet the higher the risk
+            // of sudden power loss causing loss of data. 15s is a resonable number.
+            max_lost_work:         15,
+            // Allows mechanical drives to spin down when inactive.
+            laptop_mode:           2,
+            radeon:                RadeonConfig::new("auto", "performance", "auto"),
+            scsi_link_policies:    vec!["med_power_with_dipm".into(), "medium_power".into()],
+            disks:                 DiskConfig::default(),
+            audio:                 AudioConfig {
+                power_save:            10,
+                power_save_controller: true,
+            },
+            wifi:                  WifiConfig { power_save: None, power_level: 1 },
+            backlight:             BacklightConfig { screen: Some(40), keyboard: Some(50) },
+            pci_runtime_pm:        true,
+            aspm:                  AspmConfig::default(),
+            cpus:                  CpuConfig::default(),
+            cpufreq:               CpuFreqConfig {
+                pstate_governor:               "powersave".into(),
+                governor:                      "schedutil".into(),
+                max_percent:                   100,
+                p_core_max_percent:            None,
+                e_core_max_percent:            None,
+                energy_performance_preference: vec!["balance_performance".into()],
+                energy_perf_bias:              Some(6),
+                boost:                         Some(true),
+            },
+            pstate:                PStateConfig {
+                min_perf_pct:      0,
+                max_perf_pct:      100,
+                no_turbo:          false,
+                hwp_dynamic_boost: Some(true),
+            },
+            powercap:              BTreeMap::new(),
+            model_limits:          Some(Profile::Balanced),
+        }
+    }
+
+    pub fn performance() -> Self {
+        Self {
+            base:                  None,
+            description:           "Maximizes performance at the cost of power usage".into(),
+            acpi_platform_profile: "performance".into(),
+            max_lost_work:         15,
+            laptop_mode:           0,
+            radeon:                RadeonConfig::new("high", "performance", "auto"),
+            scsi_link_policies:    vec!["med_power_with_dipm".into(), "max_performance".into()],
+            disks:                 DiskConfig::default(),
+            // Powering the codec up and down can be heard as pops.
+            audio:                 AudioConfig::disabled(),
+            wifi:                  WifiConfig { power_save: Some(false), power_level: 1 },
+            backlight:             BacklightConfig::default(),
+            pci_runtime_pm:        false,
+            aspm:                  AspmConfig::default(),
+            cpus:                  CpuConfig::default(),
+            cpufreq:               CpuFreqConfig {
+                pstate_governor:               "performance".into(),
+                governor:                      "performance".into(),
+                max_percent:                   100,
+                p_core_max_percent:            None,
+                e_core_max_percent:            None,
+                energy_performance_preference: vec!["performance".into()],
+                energy_perf_bias:              Some(0),
+                boost:                         Some(true),
+            },
+            pstate:                PStateConfig {
+                min_perf_pct:      0,
+                max_perf_pct:      100,
+                no_turbo:          false,
+                hwp_dynamic_boost: Some(true),
+            },
+            powercap:              BTreeMap::new(),
+            model_limits:          Some(Profile::Performance),
+        }
+    }
+
+    pub fn battery() -> Self {
+        Self {
+            base:                  None,
+            description:           "Reduces power usage to extend battery life".into(),
+            acpi_platform_profile: "low-power".into(),
+            max_lost_work:         15,
+            laptop_mode:           2,
+            radeon:                RadeonConfig::new("low", "battery", "low"),
+            scsi_link_policies:    vec!["min_power".into(), "min_power".into()],
+            disks:                 DiskConfig {
+                apm_level:            Some(127),
+                autosuspend_delay_ms: Some(15_000),
+            },
+            audio:                 AudioConfig {
+                power_save:            1,
+                power_save_controller: true,
+            },
+            wifi:                  WifiConfig { power_save: Some(true), power_level: 5 },
+            backlight:             BacklightConfig { screen: Some(10), keyboard: Some(0) },
+            pci_runtime_pm:        true,
+            aspm:                  AspmConfig::default(),
+            cpus:                  CpuConfig::default(),
+            cpufreq:               CpuFreqConfig {
+                pstate_governor:               "powersave".into(),
+                governor:                      "conservative".into(),
+                max_percent:                   50,
+                p_core_max_percent:            None,
+                e_core_max_percent:            None,
+                energy_performance_preference: vec!["power".into(), "balance_power".into()],
+                energy_perf_bias:              Some(15),
+                boost:                         Some(false),
+            },
+            pstate:                PStateConfig {
+                min_perf_pct:      0,
+                max_perf_pct:      50,
+                no_turbo:          true,
+                hwp_dynamic_boost: None,
+            },
+            powercap:              BTreeMap::new(),
+            model_limits:          Some(Profile::Battery),
+        }
+    }
+
+    /// The built-in definition of a profile, if there is one.
+    pub fn builtin(name: &str) -> Option<Self> {
+        Profile::from_id(name).map(|profile| match profile {
+            Profile::Battery => Self::battery(),
+            Profile::Balanced => Self::balanced(),
+            Profile::Performance => Self::performance(),
+        })
+    }
+
+    /// Loads the definition of a profile. Built-in profiles fall back to their built-in
+    /// definition if the override is missing or invalid.
+    pub fn load(name: &str) -> Result<Self, ConfigError> {
+        let path = definition_path(name)?;
+
+        match (Self::read(name, &path), Self::builtin(name)) {
+            (Ok(Some(config)), _) => {
+                log::info!("using profile definition from {:?}", path);
+                Ok(config)
+            }
+            (Ok(None), Some(builtin)) => Ok(builtin),
+            (Ok(None), None) => Err(ConfigError::NotFound(name.to_owned())),
+            (Err(why), Some(builtin)) => {
+                log::error!("{}; using built-in definition", why);
+                Ok(builtin)
+            }
+            (Err(why), None) => Err(why),
+        }
+    }
+
+    /// Reads the definition of a profile from `path`, if it exists.
+    fn read(name: &str, path: &Path) -> Result<Option<Self>, ConfigError> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(why) if why.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(why) => return Err(ConfigError::Read(path.to_owned(), why)),
+        };
+
+        Self::parse(name, &contents)
+            .map(Some)
+            .map_err(|why| ConfigError::Parse(path.to_owned(), why))
+    }
+
+    /// Parses a definition, applying its keys on top of the profile it is based on. That is the
+    /// profile named by the `base` key, or else the built-in profile of the same name, or else
+    /// the balanced profile.
+    pub fn parse(name: &str, contents: &str) -> Result<Self, serde_json::Error> {
+        // Parsing into the typed structure first reports errors with their line and column.
+        let parsed: Self = serde_json::from_str(contents)?;
+
+        let base = match parsed.base {
+            Some(profile) => Self::builtin(profile.id()),
+            None => Self::builtin(name),
+        };
+
+        let mut value = serde_json::to_value(base.unwrap_or_default())?;
+        merge(&mut value, serde_json::from_str(contents)?);
+        serde_json::from_value(value)
+    }
+
+    /// Validates a definition and stores it, creating or replacing the named profile.
+    pub fn create(name: &str, contents: &str) -> Result<Self, ConfigError> {
+        let path = definition_path(name)?;
+        let config =
+            Self::parse(name, contents).map_err(|why| ConfigError::Parse(path.clone(), why))?;
+
+        fs::create_dir_all(sysroot::path(PROFILES_DIR))
+            .and_then(|_| fs::write(&path, contents))
+            .map_err(|why| ConfigError::Write(path, why))?;
+
+        Ok(config)
+    }
+}
+
+/// Names of the built-in profiles, followed by those defined in [`PROFILES_DIR`].
+pub fn profile_names() -> Vec<String> {
+    let mut names: Vec<String> =
+        Profile::BUILTIN.iter().map(|profile| profile.id().to_owned()).collect();
+
+    let entries = match fs::read_dir(sysroot::path(PROFILES_DIR)) {
+        Ok(entries) => entries,
+        Err(why) => {
+            if why.kind() != io::ErrorKind::NotFound {
+                log::warn!("failed to read {}: {}", PROFILES_DIR, why);
+            }
+
+            return names;
+        }
+    };
+
+    let mut defined: Vec<String> = entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter_map(|file| file.strip_suffix(".json").map(String::from))
+        .filter(|name| valid_name(name) && Profile::from_id(name).is_none())
+        .collect();
+
+    defined.sort();
+    names.extend(defined);
+    names
+}
+
+/// Profile names may only contain ASCII letters, digits, `-` and `_`. `restore` is taken by the
+/// startup setting, which would never select a profile of that name.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name != "restore"
+        && name.len() <= 64
+        && name.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_')
+}
+
+fn definition_path(name: &str) -> Result<PathBuf, ConfigError> {
+    if valid_name(name) {
+        Ok(sysroot::path(PROFILES_DIR).join([name, ".json"].concat()))
+    } else {
+        Err(ConfigError::InvalidName(name.to_owned()))
+    }
+}
+
+/// Recursively replaces the keys of `base` with those found in `overlay`.
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn override_keeps_builtin_values() {
+        let config = ProfileConfig::parse(
+            "battery",
+            r#"{ "laptop_mode": 5, "pstate": { "max_perf_pct": 30 } }"#,
+        )
+        .unwrap();
+
+        let mut expected = ProfileConfig::battery();
+        expected.laptop_mode = 5;
+        expected.pstate.max_perf_pct = 30;
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn named_profile_uses_base() {
+        let config = ProfileConfig::parse(
+            "quiet",
+            r#"{ "base": "battery", "description": "Quiet", "cpufreq": { "max_percent": 30 } }"#,
+        )
+        .unwrap();
+
+        let mut expected = ProfileConfig::battery();
+        expected.base = Some(Profile::Battery);
+        expected.description = "Quiet".into();
+        expected.cpufreq.max_percent = 30;
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn errors_report_line() {
+        let err = ProfileConfig::parse(
+            "balanced",
+            "{\n  \"laptop_mode\": 2,\n  \"max_lost_wrok\": 15\n}",
+        )
+        .unwrap_err();
+        assert_eq!(err.line(), 3);
+
+        let err = ProfileConfig::parse("balanced", "{\n  \"laptop_mode\": \"two\"\n}").unwrap_err();
+        assert_eq!(err.line(), 2);
+    }
+
+    #[test]
+    fn builtin_round_trip() {
+        for profile in &Profile::BUILTIN {
+            let builtin = ProfileConfig::builtin(profile.id()).unwrap();
+            let json = serde_json::to_string(&builtin).unwrap();
+            assert_eq!(ProfileConfig::parse(profile.id(), &json).unwrap(), builtin);
+        }
+    }
+
+    #[test]
+    fn name_validation() {
+        assert!(valid_name("render-farm"));
+        assert!(valid_name("quiet_2"));
+        assert!(!valid_name(""));
+        assert!(!valid_name("../battery"));
+        assert!(!valid_name("with space"));
+        assert!(!valid_name("restore"));
+    }
+}
@@ -0,0 +1,126 @@
+// Copyright 2022 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Profiles held by applications for as long as they need them.
+
+use crate::{HoldEntry, Profile};
+
+/// A request for a profile by an application, until it is released.
+struct Hold {
+    cookie:         u32,
+    profile:        Profile,
+    reason:         String,
+    application_id: String,
+    /// The unique bus name of the client, whose holds are released when it disconnects.
+    owner:          Option<String>,
+}
+
+#[derive(Default)]
+pub struct Holds {
+    holds:       Vec<Hold>,
+    last_cookie: u32,
+}
+
+impl Holds {
+    pub fn is_empty(&self) -> bool { self.holds.is_empty() }
+
+    /// Adds a hold on the battery or performance profile, returning its cookie.
+    pub fn add(
+        &mut self,
+        profile: &str,
+        reason: &str,
+        application_id: &str,
+        owner: Option<String>,
+    ) -> Result<u32, String> {
+        let profile = match Profile::from_id(profile) {
+            Some(profile @ Profile::Battery) | Some(profile @ Profile::Performance) => profile,
+            _ => return Err(format!("cannot hold the '{}' profile", profile)),
+        };
+
+        self.last_cookie = self.last_cookie.wrapping_add(1).max(1);
+        let cookie = self.last_cookie;
+        self.holds.push(Hold {
+            cookie,
+            profile,
+            reason: reason.to_owned(),
+            application_id: application_id.to_owned(),
+            owner,
+        });
+
+        Ok(cookie)
+    }
+
+    /// Releases a hold, which only the client which took it may do.
+    pub fn release(&mut self, cookie: u32, owner: Option<&str>) -> Result<(), String> {
+        let index = self
+            .holds
+            .iter()
+            .position(|hold| hold.cookie == cookie)
+            .ok_or_else(|| format!("no hold has the cookie {}", cookie))?;
+
+        if self.holds[index].owner.as_deref() != owner {
+            return Err(format!("the hold {} belongs to another client", cookie));
+        }
+
+        self.holds.remove(index);
+        Ok(())
+    }
+
+    /// Releases the holds of a client, returning their cookies.
+    pub fn release_owner(&mut self, owner: &str) -> Vec<u32> {
+        let (released, kept) =
+            self.holds.drain(..).partition(|hold| hold.owner.as_deref() == Some(owner));
+        self.holds = kept;
+        released.into_iter().map(|hold: Hold| hold.cookie).collect()
+    }
+
+    /// Releases every hold, returning their cookies.
+    pub fn release_all(&mut self) -> Vec<u32> {
+        self.holds.drain(..).map(|hold| hold.cookie).collect()
+    }
+
+    /// The profile which the holds select, where battery takes precedence over performance.
+    pub fn profile(&self) -> Option<Profile> {
+        let held = |profile| self.holds.iter().any(|hold| hold.profile == profile);
+        [Profile::Battery, Profile::Performance].iter().copied().find(|&profile| held(profile))
+    }
+
+    pub fn entries(&self) -> Vec<HoldEntry> {
+        self.holds
+            .iter()
+            .map(|hold| {
+                let profile = hold.profile.id().to_owned();
+                (hold.cookie, profile, hold.reason.clone(), hold.application_id.clone())
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn battery_holds_beat_performance() {
+        let mut holds = Holds::default();
+        assert!(holds.add("balanced", "", "", None).is_err());
+
+        let game = holds.add("performance", "gaming", "com.example.Game", None).unwrap();
+        assert_eq!(holds.profile(), Some(Profile::Performance));
+
+        let owner = Some(":1.42".to_owned());
+        let saver = holds.add("battery", "low battery", "com.example.Saver", owner).unwrap();
+        assert_ne!(game, saver);
+        assert_eq!(holds.profile(), Some(Profile::Battery));
+
+        assert!(holds.release(saver, Some(":1.43")).is_err());
+        assert_eq!(holds.release_owner(":1.42"), vec![saver]);
+        assert_eq!(holds.profile(), Some(Profile::Performance));
+
+        assert!(holds.release(game, Some(":1.42")).is_err());
+        assert!(holds.release(game, None).is_ok());
+        assert!(holds.release(game, None).is_err());
+        assert_eq!(holds.profile(), None);
+    }
+}
@@ -6,16 +6,17 @@ use dbus::{
     arg,
     channel::{MatchingReceiver, Sender},
     message::{MatchRule, Message},
-    nonblock::SyncConnection,
+    nonblock::{stdintf::org_freedesktop_dbus::RequestNameReply, SyncConnection},
+    strings::BusName,
 };
 use dbus_crossroads::{Crossroads, IfaceBuilder, MethodErr};
 use dbus_tokio::connection;
 use std::{
     fmt::Debug,
-    fs,
+    fs, io, mem,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, Mutex, Weak,
     },
     thread,
     time::Duration,
@@ -28,6 +29,7 @@ use tokio::{
 use futures::future::FutureExt;
 
 use crate::{
+    aspm::{self, LinkState},
     charge_thresholds::{
         get_charge_profiles, get_charge_thresholds, set_charge_thresholds, ChargeProfile,
     },
@@ -35,17 +37,57 @@ use crate::{
     errors::ProfileError,
     fan::FanDaemon,
     graphics::{Graphics, GraphicsMode},
+    hardware::{self, Limit, LimitEntry, ModelProfile},
     hid_backlight,
     hotplug::{mux, Detect, HotPlugDetect},
     kernel_parameters::{KernelParameter, NmiWatchdog},
-    polkit, Power, DBUS_IFACE, DBUS_NAME, DBUS_PATH,
+    plan::{Failure, Operation, Plan, ReportEntry, Step},
+    polkit,
+    powercap::{self, ConstraintEntry, Zone},
+    sysroot, systemd, HoldEntry, Power, Profile, DBUS_IFACE, DBUS_NAME, DBUS_PATH,
 };
 
+mod apps;
+mod autoswitch;
+mod config;
+mod holds;
+mod offline;
+mod power_profiles;
 mod profiles;
+mod properties;
+mod snapshot;
+mod state;
+mod thermald;
+
+use self::{
+    apps::AppWatcher,
+    autoswitch::AutoSwitch,
+    config::{AudioConfig, DaemonConfig, ProfileConfig},
+    holds::Holds,
+    offline::Offline,
+    profiles::*,
+    properties::Properties,
+    snapshot::Snapshot,
+    state::{Reason, Startup, State},
+    thermald::Thermald,
+};
 
-use self::profiles::*;
-
+const PROFILE_POLICY: &str = "com.system76.powerdaemon.set-profile";
+const HOLD_POLICY: &str = "com.system76.powerdaemon.hold-profile";
+const CREATE_PROFILE_POLICY: &str = "com.system76.powerdaemon.create-profile";
+const STARTUP_POLICY: &str = "com.system76.powerdaemon.set-startup-profile";
+const AUTO_SWITCH_POLICY: &str = "com.system76.powerdaemon.set-auto-switch";
+const RESTORE_POLICY: &str = "com.system76.powerdaemon.restore-defaults";
+const GRAPHICS_POLICY: &str = "com.system76.powerdaemon.set-graphics";
+const GRAPHICS_POWER_POLICY: &str = "com.system76.powerdaemon.set-graphics-power";
 const THRESHOLD_POLICY: &str = "com.system76.powerdaemon.set-charge-thresholds";
+const LIMITS_POLICY: &str = "com.system76.powerdaemon.set-power-limits";
+
+/// How many ticks of the main loop pass between refreshes of the state of thermald.
+const THERMALD_REFRESH_TICKS: u32 = 10;
+
+/// How many ticks of the main loop pass between reads of the slow properties.
+const PROPERTIES_REFRESH_TICKS: u32 = 10;
 
 static CONTINUE: AtomicBool = AtomicBool::new(true);
 
@@ -73,46 +115,319 @@ static PCI_RUNTIME_PM: AtomicBool = AtomicBool::new(false);
 // TODO: Whitelist system76 hardware that's known to work with this setting.
 pub(crate) fn pci_runtime_pm_support() -> bool { PCI_RUNTIME_PM.load(Ordering::SeqCst) }
 
+/// Maps the name of a built-in profile to its identifier, leaving other names as they are.
+fn profile_id(name: &str) -> &str { Profile::from_id(name).map_or(name, |profile| profile.id()) }
+
 struct PowerDaemon {
     initial_set:     bool,
     graphics:        Graphics,
     power_profile:   String,
     profile_errors:  Vec<ProfileError>,
+    last_report:     Vec<Step>,
+    snapshot:        Snapshot,
+    config:          DaemonConfig,
+    state:           State,
+    autoswitch:      AutoSwitch,
+    /// Watches for the processes of the application rules, if there are any.
+    apps:            Option<AppWatcher>,
+    /// The profile applied for a running application, and the one which preceded it.
+    app_profile:     Option<(String, String)>,
+    holds:           Holds,
+    /// The profile which was active before the first of the current holds.
+    unheld_profile:  Option<String>,
+    /// The properties as they were last signalled.
+    properties:      Option<Properties>,
+    thermald:        Thermald,
+    offline:         Offline,
+    /// The systemd units to start or stop, which the main loop does through the connection.
+    units:           Vec<(String, bool)>,
     dbus_connection: Arc<SyncConnection>,
 }
 
 impl PowerDaemon {
     fn new(dbus_connection: Arc<SyncConnection>) -> Result<PowerDaemon, String> {
         let graphics = Graphics::new().map_err(err_str)?;
+        let config = DaemonConfig::load();
+        let apps = if config.apps.is_empty() { None } else { Some(AppWatcher::new(&config.apps)) };
         Ok(PowerDaemon {
             initial_set: false,
             graphics,
             power_profile: String::new(),
             profile_errors: Vec::new(),
+            last_report: Vec::new(),
+            snapshot: Snapshot::default(),
+            config,
+            state: State::load(),
+            autoswitch: AutoSwitch::new(),
+            apps,
+            app_profile: None,
+            holds: Holds::default(),
+            unheld_profile: None,
+            properties: None,
+            thermald: Thermald::default(),
+            offline: Offline::default(),
+            units: Vec::new(),
             dbus_connection,
         })
     }
 
-    fn apply_profile(
+    fn autoswitch_enabled(&self) -> bool {
+        self.state.autoswitch.unwrap_or(self.config.autoswitch.enabled)
+    }
+
+    /// Applies the profile configured for the AC state when the adapter is plugged or unplugged.
+    ///
+    /// Only changes of the AC state are acted upon, so a profile selected manually stays active
+    /// until the next plug event.
+    fn step_autoswitch(&mut self) {
+        let online = match self.autoswitch.step() {
+            Some(online) => online,
+            None => return,
+        };
+
+        if !self.autoswitch_enabled() {
+            return;
+        }
+
+        let (profile, reason) = if online {
+            (self.config.autoswitch.ac.clone(), Reason::Ac)
+        } else {
+            (self.config.autoswitch.battery.clone(), Reason::Battery)
+        };
+
+        log::info!("Switching to the {} profile ({})", profile, reason);
+        if let Err(why) = self.switch_unless_held(&profile, reason) {
+            log::warn!("Failed to switch profile: {}", why);
+        }
+    }
+
+    /// Applies the profile of the first application rule with a running process, and switches
+    /// back to the profile which preceded it once none of them run.
+    ///
+    /// A profile selected while the application runs is kept when it exits.
+    fn step_apps(&mut self) {
+        let profile = match self.apps {
+            Some(ref mut apps) => apps.step(&self.config.apps).map(String::from),
+            None => return,
+        };
+
+        match (profile, self.app_profile.take()) {
+            (Some(profile), Some((applied, previous))) if applied == profile => {
+                self.app_profile = Some((applied, previous));
+            }
+            (Some(profile), app_profile) => {
+                let previous = app_profile.map_or_else(|| self.unheld(), |(_, p)| p);
+                log::info!("Switching to the {} profile for a running application", profile);
+                if let Err(why) = self.switch_unless_held(&profile, Reason::App) {
+                    log::warn!("Failed to switch profile: {}", why);
+                }
+                self.app_profile = Some((profile, previous));
+            }
+            (None, Some((applied, previous))) => {
+                if profile_id(&applied) != profile_id(&self.unheld()) || previous.is_empty() {
+                    return;
+                }
+
+                log::info!("Switching back to the {} profile", previous);
+                if let Err(why) = self.switch_unless_held(&previous, Reason::App) {
+                    log::warn!("Failed to switch profile: {}", why);
+                }
+            }
+            (None, None) => (),
+        }
+    }
+
+    /// The profile which is in effect without the holds, and is applied once they are released.
+    fn unheld(&self) -> String {
+        if self.holds.is_empty() {
+            self.power_profile.clone()
+        } else {
+            self.unheld_profile.clone().unwrap_or_default()
+        }
+    }
+
+    /// Switches profile automatically, or, while profiles are held, only changes the profile
+    /// which is applied once the holds are released, so that the held profile stays in effect.
+    fn switch_unless_held(&mut self, name: &str, reason: Reason) -> Result<(), String> {
+        if !self.holds.is_empty() {
+            log::info!("keeping the held profile, and switching to {} once it is released", name);
+            self.unheld_profile = Some(name.to_owned());
+            return Ok(());
+        }
+
+        self.switch_profile(name, reason)
+    }
+
+    /// Holds a profile on behalf of a client, until it releases the hold or disconnects.
+    fn hold(
         &mut self,
-        func: fn(&mut Vec<ProfileError>, bool),
-        name: &str,
-    ) -> Result<(), String> {
+        owner: Option<String>,
+        profile: &str,
+        reason: &str,
+        application_id: &str,
+    ) -> Result<u32, String> {
+        let was_empty = self.holds.is_empty();
+        let cookie = self.holds.add(profile, reason, application_id, owner)?;
+        if was_empty {
+            self.unheld_profile = Some(self.power_profile.clone());
+        }
+
+        // The hold is taken even if the profile is not fully applied, which is reported like any
+        // other profile switch, so that the client can still release it.
+        log::info!("{} holds the {} profile ({}): {}", application_id, profile, cookie, reason);
+        if let Err(why) = self.apply_holds() {
+            log::warn!("Failed to apply held profile: {}", why);
+        }
+
+        Ok(cookie)
+    }
+
+    /// Releases a hold taken by the client with the unique bus name `owner`.
+    fn release(&mut self, owner: Option<&str>, cookie: u32) -> Result<(), String> {
+        self.holds.release(cookie, owner)?;
+        log::info!("released hold {}", cookie);
+        if let Err(why) = self.apply_holds() {
+            log::warn!("Failed to switch profile: {}", why);
+        }
+
+        Ok(())
+    }
+
+    /// Releases the holds of a client which disconnected from the bus.
+    fn release_owner(&mut self, owner: &str) {
+        let released = self.holds.release_owner(owner);
+        if released.is_empty() {
+            return;
+        }
+
+        log::info!("releasing the holds of {}, which disconnected", owner);
+        self.send_released(&released);
+        if let Err(why) = self.apply_holds() {
+            log::warn!("Failed to switch profile: {}", why);
+        }
+    }
+
+    /// Signals that holds were released other than by the client which took them.
+    fn send_released(&self, cookies: &[u32]) {
+        for &cookie in cookies {
+            let mut messages = vec![Message::new_signal(DBUS_PATH, DBUS_NAME, "ProfileReleased")
+                .unwrap()
+                .append1(cookie)];
+            if self.config.power_profiles {
+                messages.extend(power_profiles::profile_released(cookie));
+            }
+
+            for message in messages {
+                if let Err(()) = self.dbus_connection.send(message) {
+                    log::error!("failed to send profile released message");
+                }
+            }
+        }
+    }
+
+    /// Signals the properties which changed since they were last read, including the slow ones
+    /// if `all` is set.
+    fn step_properties(&mut self, all: bool) {
+        let properties = Properties::read(self, all);
+        let message = self.properties.as_ref().and_then(|last| properties.changed_message(last));
+        if let Some(message) = message {
+            if let Err(()) = self.dbus_connection.send(message) {
+                log::error!("failed to send properties changed message");
+            }
+        }
+
+        self.properties = Some(properties);
+    }
+
+    /// Signals the active profile and its holds through the interface of power-profiles-daemon.
+    fn send_power_profiles_changed(&self) {
+        if !self.config.power_profiles {
+            return;
+        }
+
+        for message in power_profiles::properties_changed(self) {
+            if let Err(()) = self.dbus_connection.send(message) {
+                log::error!("failed to send power profiles properties changed message");
+            }
+        }
+    }
+
+    /// Applies the profile which the holds select, or the one which preceded them once they are
+    /// all released.
+    fn apply_holds(&mut self) -> Result<(), String> {
+        self.send_power_profiles_changed();
+        match self.holds.profile() {
+            Some(profile) => self.switch_profile(profile.id(), Reason::Hold),
+            None => match self.unheld_profile.take() {
+                Some(profile) if !profile.is_empty() => self.switch_profile(&profile, Reason::Hold),
+                _ => Ok(()),
+            },
+        }
+    }
+
+    fn switch_profile(&mut self, name: &str, reason: Reason) -> Result<(), String> {
+        match Profile::from_id(name) {
+            Some(profile) => self.apply_profile(profile.id(), profile.title(), reason),
+            None => self.apply_profile(name, name, reason),
+        }
+    }
+
+    /// Loads the definition of a profile, with the settings of the daemon which override it.
+    fn load_profile(&self, id: &str) -> Result<ProfileConfig, String> {
+        let mut config = ProfileConfig::load(id).map_err(err_str)?;
+        if !self.config.audio.power_save {
+            config.audio = AudioConfig::disabled();
+        }
+
+        Ok(config)
+    }
+
+    fn apply_profile(&mut self, id: &str, name: &str, reason: Reason) -> Result<(), String> {
         if self.power_profile == name {
             log::info!("profile was already set");
             return Ok(());
         }
 
-        func(&mut self.profile_errors, self.initial_set);
-
-        let message =
-            Message::new_signal(DBUS_PATH, DBUS_NAME, "PowerProfileSwitch").unwrap().append1(name);
-
-        if let Err(()) = self.dbus_connection.send(message) {
-            log::error!("failed to send power profile switch message");
+        let config = self.load_profile(id)?;
+        let mut plan = Plan::new();
+        apply(
+            &mut plan,
+            &config,
+            &self.state.limits,
+            &mut self.thermald,
+            &mut self.offline,
+            &mut self.profile_errors,
+            self.initial_set,
+        );
+        self.snapshot.record(plan.steps());
+        self.units.extend(plan.units());
+        self.last_report = plan.into_steps();
+
+        // The reason has a signal of its own, as subscribers unpack the profile alone.
+        let messages = [
+            Message::new_signal(DBUS_PATH, DBUS_NAME, "PowerProfileSwitch").unwrap().append1(name),
+            Message::new_signal(DBUS_PATH, DBUS_NAME, "PowerProfileSwitchReason")
+                .unwrap()
+                .append2(name, reason.as_str()),
+        ];
+
+        for message in messages {
+            if let Err(()) = self.dbus_connection.send(message) {
+                log::error!("failed to send power profile switch message");
+            }
         }
 
         self.power_profile = name.into();
+        self.step_properties(false);
+        self.send_power_profiles_changed();
+
+        if reason.is_choice() {
+            self.state.profile = Some(id.into());
+            self.state.reason = Some(reason);
+            if let Err(why) = self.state.save() {
+                log::error!("failed to save daemon state: {}", why);
+            }
+        }
 
         if self.profile_errors.is_empty() {
             Ok(())
@@ -125,20 +440,64 @@ impl PowerDaemon {
             Err(error_message)
         }
     }
-}
 
-impl Power for PowerDaemon {
-    fn battery(&mut self) -> Result<(), String> {
-        self.apply_profile(battery, "Battery").map_err(err_str)
+    /// The power limits which the active profile sets.
+    fn configured_limits(&self) -> ModelProfile {
+        if self.power_profile.is_empty() {
+            return ModelProfile::default();
+        }
+
+        let profile = self.load_profile(profile_id(&self.power_profile)).ok();
+        model_limits(profile.and_then(|config| config.model_limits), &self.state.limits)
     }
 
-    fn balanced(&mut self) -> Result<(), String> {
-        self.apply_profile(balanced, "Balanced").map_err(err_str)
+    /// Sets the power limits of the active profile again, after the custom limits changed.
+    fn apply_limits(&mut self) -> Result<(), String> {
+        let limits = self.configured_limits();
+        let manages_powercap = !self.power_profile.is_empty()
+            && self
+                .load_profile(profile_id(&self.power_profile))
+                .map_or(false, |config| !config.powercap.is_empty());
+
+        let mut plan = Plan::new();
+        plan.setting("thermald");
+        let manages_limits = manages_powercap || limits != ModelProfile::default();
+        coordinate_thermald(&mut plan, &mut self.thermald, manages_limits);
+
+        let mut result = Ok(());
+        if limits != ModelProfile::default() {
+            plan.setting("model_limits");
+            result = limits.set(&mut plan).map_err(err_str);
+        }
+
+        self.snapshot.record(plan.steps());
+        self.units.extend(plan.units());
+        result
     }
 
-    fn performance(&mut self) -> Result<(), String> {
-        self.apply_profile(performance, "Performance").map_err(err_str)
+    /// Replaces the pending unit changes of the last report with their results.
+    fn finish_units(&mut self, results: Vec<(String, bool, Option<Failure>)>) {
+        for (unit, active, failure) in results {
+            let step = self.last_report.iter_mut().find(|step| {
+                step.pending
+                    && matches!(step.operation, Operation::Unit { unit: ref name, new, .. }
+                        if *name == unit && new == active)
+            });
+
+            if let Some(step) = step {
+                step.pending = false;
+                step.failure = failure;
+            }
+        }
     }
+}
+
+impl Power for PowerDaemon {
+    fn battery(&mut self) -> Result<(), String> { self.set_profile(Profile::Battery.id()) }
+
+    fn balanced(&mut self) -> Result<(), String> { self.set_profile(Profile::Balanced.id()) }
+
+    fn performance(&mut self) -> Result<(), String> { self.set_profile(Profile::Performance.id()) }
 
     fn get_external_displays_require_dgpu(&mut self) -> Result<bool, String> {
         self.graphics.get_external_displays_require_dgpu().map_err(err_str)
@@ -164,6 +523,192 @@ impl Power for PowerDaemon {
 
     fn get_profile(&mut self) -> Result<String, String> { Ok(self.power_profile.clone()) }
 
+    fn set_profile(&mut self, name: &str) -> Result<(), String> {
+        // A profile chosen by the user takes the place of those held by applications.
+        if !self.holds.is_empty() {
+            log::info!("releasing every hold for the profile chosen by the user");
+            let released = self.holds.release_all();
+            self.send_released(&released);
+            self.unheld_profile = None;
+        }
+
+        self.switch_profile(name, Reason::User)
+    }
+
+    fn get_profiles(&mut self) -> Result<Vec<String>, String> { Ok(config::profile_names()) }
+
+    fn hold_profile(
+        &mut self,
+        profile: &str,
+        reason: &str,
+        application_id: &str,
+    ) -> Result<u32, String> {
+        self.hold(None, profile, reason, application_id)
+    }
+
+    fn release_profile(&mut self, cookie: u32) -> Result<(), String> { self.release(None, cookie) }
+
+    fn get_holds(&mut self) -> Result<Vec<HoldEntry>, String> { Ok(self.holds.entries()) }
+
+    fn describe_profile(&mut self, name: &str) -> Result<String, String> {
+        let config = ProfileConfig::load(profile_id(name)).map_err(err_str)?;
+        serde_json::to_string_pretty(&config).map_err(err_str)
+    }
+
+    fn plan_profile(
+        &mut self,
+        name: &str,
+    ) -> Result<Vec<(String, String, String, String)>, String> {
+        let config = self.load_profile(profile_id(name))?;
+        let mut plan = Plan::dry_run();
+        let mut errors = Vec::new();
+        apply(
+            &mut plan,
+            &config,
+            &self.state.limits,
+            &mut self.thermald,
+            &mut self.offline,
+            &mut errors,
+            self.initial_set,
+        );
+
+        for error in errors {
+            log::warn!("planning {} profile: {}", name, error);
+        }
+
+        Ok(plan.operations().map(Operation::to_tuple).collect())
+    }
+
+    fn create_profile(&mut self, name: &str, definition: &str) -> Result<(), String> {
+        let name = profile_id(name);
+        ProfileConfig::create(name, definition).map_err(err_str)?;
+        log::info!("created profile definition for {}", name);
+
+        // The active profile is applied again, as switching to it would otherwise do nothing.
+        if name == profile_id(&self.power_profile) {
+            log::info!("applying the new definition of the active profile");
+            let active = mem::take(&mut self.power_profile);
+            let reason = if self.holds.is_empty() { Reason::User } else { Reason::Hold };
+            return self.switch_profile(profile_id(&active), reason);
+        }
+
+        Ok(())
+    }
+
+    fn get_startup_profile(&mut self) -> Result<String, String> {
+        Ok(self.state.startup.to_string())
+    }
+
+    fn set_startup_profile(&mut self, startup: &str) -> Result<(), String> {
+        let startup = match Startup::parse(startup) {
+            Startup::Profile(name) => {
+                let id = profile_id(&name);
+                ProfileConfig::load(id).map_err(err_str)?;
+                Startup::Profile(id.to_owned())
+            }
+            Startup::Restore => Startup::Restore,
+        };
+
+        log::info!("setting startup profile to {}", startup);
+        self.state.startup = startup;
+        self.state.save().map_err(err_str)
+    }
+
+    fn get_last_apply_report(&mut self) -> Result<Vec<ReportEntry>, String> {
+        Ok(self.last_report.iter().map(Step::to_report).collect())
+    }
+
+    fn restore_defaults(&mut self) -> Result<(), String> {
+        if self.snapshot.is_empty() {
+            log::info!("no tunables to restore");
+            return Ok(());
+        }
+
+        log::info!("restoring the original values of tunables");
+        let mut plan = Plan::new();
+        let errors = self.snapshot.restore(&mut plan);
+        self.units.extend(plan.units());
+        self.thermald.restored();
+        self.offline.restored();
+
+        // No profile is in effect anymore, so setting any profile applies it again.
+        self.power_profile.clear();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            let mut error_message = String::from("Errors found when restoring tunables:");
+            for error in errors {
+                error_message = format!("{}\n    - {}", error_message, error);
+            }
+
+            Err(error_message)
+        }
+    }
+
+    fn get_auto_switch(&mut self) -> Result<bool, String> { Ok(self.autoswitch_enabled()) }
+
+    fn set_auto_switch(&mut self, enabled: bool) -> Result<(), String> {
+        log::info!("setting automatic profile switching to {}", enabled);
+        self.state.autoswitch = Some(enabled);
+        self.state.save().map_err(err_str)
+    }
+
+    fn get_aspm(&mut self) -> Result<(String, Vec<LinkState>), String> {
+        Ok((aspm::policy().unwrap_or_default(), aspm::link_states()))
+    }
+
+    fn get_limits(&mut self) -> Result<Vec<LimitEntry>, String> {
+        let configured = self.configured_limits();
+        let effective = ModelProfile::get().map_err(err_str)?;
+        let bounds = hardware::current_model().and_then(|model| model.bounds).unwrap_or_default();
+
+        let show = |value: Option<u8>| value.map(|value| value.to_string()).unwrap_or_default();
+        Ok(Limit::ALL
+            .iter()
+            .map(|&limit| {
+                let (min, max) = bounds.get(limit).unzip();
+                (
+                    limit.name().to_owned(),
+                    show(configured.limit(limit)),
+                    show(effective.limit(limit)),
+                    show(min),
+                    show(max),
+                )
+            })
+            .collect())
+    }
+
+    fn set_limit(&mut self, name: &str, value: u8) -> Result<(), String> {
+        let limit =
+            Limit::from_name(name).ok_or_else(|| format!("unknown power limit '{}'", name))?;
+        let (min, max) = hardware::current_model()
+            .and_then(|model| model.bounds?.get(limit))
+            .ok_or_else(|| format!("{} cannot be customized on this model", name))?;
+        if value < min || value > max {
+            return Err(format!("{} must be between {} and {}", name, min, max));
+        }
+
+        log::info!("setting custom {} to {}", name, value);
+        *self.state.limits.limit_mut(limit) = Some(value);
+        self.state.save().map_err(err_str)?;
+        self.apply_limits()
+    }
+
+    fn reset_limits(&mut self) -> Result<(), String> {
+        log::info!("resetting custom power limits");
+        self.state.limits = ModelProfile::default();
+        self.state.save().map_err(err_str)?;
+        self.apply_limits()
+    }
+
+    fn get_powercap(&mut self) -> Result<Vec<ConstraintEntry>, String> {
+        let zones = powercap::zones().map_err(err_str)?;
+        Ok(zones.iter().flat_map(Zone::to_entries).collect())
+    }
+
+    fn get_thermald(&mut self) -> Result<String, String> { Ok(self.thermald.state().to_owned()) }
+
     fn get_switchable(&mut self) -> Result<bool, String> { Ok(self.graphics.can_switch()) }
 
     fn set_graphics(&mut self, vendor: &str) -> Result<(), String> {
@@ -174,7 +719,9 @@ impl Power for PowerDaemon {
             _ => GraphicsMode::Integrated,
         };
 
-        self.graphics.set_vendor(vendor).map_err(err_str)
+        let result = self.graphics.set_vendor(vendor).map_err(err_str);
+        self.step_properties(true);
+        result
     }
 
     fn get_graphics_power(&mut self) -> Result<bool, String> {
@@ -182,7 +729,9 @@ impl Power for PowerDaemon {
     }
 
     fn set_graphics_power(&mut self, power: bool) -> Result<(), String> {
-        self.graphics.set_power(power).map_err(err_str)
+        let result = self.graphics.set_power(power).map_err(err_str);
+        self.step_properties(true);
+        result
     }
 
     fn auto_graphics_power(&mut self) -> Result<(), String> {
@@ -192,8 +741,9 @@ impl Power for PowerDaemon {
     fn get_charge_thresholds(&mut self) -> Result<(u8, u8), String> { get_charge_thresholds() }
 
     fn set_charge_thresholds(&mut self, thresholds: (u8, u8)) -> Result<(), String> {
-        // NOTE: This method is not actually called by daemon
-        set_charge_thresholds(thresholds)
+        let result = set_charge_thresholds(thresholds);
+        self.step_properties(true);
+        result
     }
 
     fn get_charge_profiles(&mut self) -> Result<Vec<ChargeProfile>, String> {
@@ -212,6 +762,8 @@ pub async fn daemon() -> Result<(), String> {
     );
     PCI_RUNTIME_PM.store(pci_runtime_pm, Ordering::SeqCst);
 
+    hardware::load();
+
     log::info!("Connecting to dbus system bus");
     let (resource, c) = connection::new_system_sync().map_err(err_str)?;
 
@@ -221,14 +773,17 @@ pub async fn daemon() -> Result<(), String> {
     });
 
     let mut daemon = PowerDaemon::new(c.clone())?;
+    daemon.thermald.active = query_thermald(&c).await.unwrap_or(None);
     let nvidia_exists = !daemon.graphics.nvidia.is_empty();
 
     log::info!("Disabling NMI Watchdog (for kernel debugging only)");
-    NmiWatchdog::default().set(b"0");
+    let mut plan = Plan::new();
+    NmiWatchdog::default().set_with(&mut plan, "0");
+    daemon.snapshot.record(plan.steps());
 
     // Get the NVIDIA device ID before potentially removing it.
     let nvidia_device_id = if nvidia_exists {
-        fs::read_to_string("/sys/bus/pci/devices/0000:01:00.0/device").ok()
+        fs::read_to_string(sysroot::path("/sys/bus/pci/devices/0000:01:00.0/device")).ok()
     } else {
         None
     };
@@ -241,77 +796,216 @@ pub async fn daemon() -> Result<(), String> {
         }
     }
 
-    log::info!("Initializing with the balanced profile");
-    if let Err(why) = daemon.balanced() {
+    let startup = daemon.state.startup_profile().unwrap_or("balanced").to_owned();
+    log::info!("Initializing with the {} profile ({})", startup, daemon.state.startup);
+    if let Err(why) = daemon.switch_profile(&startup, Reason::Startup) {
         log::warn!("Failed to set initial profile: {}", why);
     }
+
+    // The stored profile may have been removed since it was last applied.
+    if daemon.power_profile.is_empty() {
+        log::info!("Initializing with the balanced profile");
+        if let Err(why) = daemon.switch_profile("balanced", Reason::Startup) {
+            log::warn!("Failed to set initial profile: {}", why);
+        }
+    }
     daemon.initial_set = true;
+    let results = set_units_active(&c, mem::take(&mut daemon.units)).await;
+    daemon.finish_units(results);
 
     log::info!("Registering dbus name {}", DBUS_NAME);
     c.request_name(DBUS_NAME, false, true, false).await.map_err(err_str)?;
 
+    let power_profiles = daemon.config.power_profiles;
+
     log::info!("Adding dbus path {} with interface {}", DBUS_PATH, DBUS_IFACE);
-    let mut cr = Crossroads::new();
-    cr.set_async_support(Some((
-        c.clone(),
-        Box::new(|x| {
-            tokio::spawn(x);
-        }),
-    )));
-    let iface_token = cr.register(DBUS_IFACE, |b| {
-        sync_action_method(b, "Performance", PowerDaemon::performance);
-        sync_action_method(b, "Balanced", PowerDaemon::balanced);
-        sync_action_method(b, "Battery", PowerDaemon::battery);
-        sync_get_method(
-            b,
-            "GetExternalDisplaysRequireDGPU",
-            "required",
-            PowerDaemon::get_external_displays_require_dgpu,
-        );
-        sync_get_method(b, "GetDefaultGraphics", "vendor", PowerDaemon::get_default_graphics);
-        sync_get_method(b, "GetGraphics", "vendor", PowerDaemon::get_graphics);
-        sync_set_method(b, "SetGraphics", "vendor", |d, s: String| d.set_graphics(&s));
-        sync_get_method(b, "GetProfile", "profile", PowerDaemon::get_profile);
-        sync_get_method(b, "GetSwitchable", "switchable", PowerDaemon::get_switchable);
-        sync_get_method(b, "GetGraphicsPower", "power", PowerDaemon::get_graphics_power);
-        sync_set_method(b, "SetGraphicsPower", "power", PowerDaemon::set_graphics_power);
-        sync_get_method(b, "GetChargeThresholds", "thresholds", PowerDaemon::get_charge_thresholds);
-        let c_clone = c.clone();
-        b.method_with_cr_async(
-            "SetChargeThresholds",
-            ("thresholds",),
-            (),
-            move |mut ctx, _cr, (thresholds,): ((u8, u8),)| {
-                let sender = ctx.message().sender().unwrap().into_static();
-                let c = c_clone.clone();
-                let res = async move {
-                    let pid = polkit::get_connection_unix_process_id(&c, sender)
-                        .await
-                        .map_err(err_str)?;
-                    let permitted = if pid == 0 {
-                        true
-                    } else {
-                        polkit::check_authorization(&c, pid, 0, THRESHOLD_POLICY)
-                            .await
-                            .map_err(err_str)?
-                    };
-                    if permitted {
-                        set_charge_thresholds(thresholds)?;
-                        Ok(())
-                    } else {
-                        Err("Operation not permitted by Polkit".to_string())
-                    }
-                };
-                async move { ctx.reply(res.await.map_err(|e| MethodErr::failed(&e))) }
-            },
-        );
-        sync_get_method(b, "GetChargeProfiles", "profiles", PowerDaemon::get_charge_profiles);
-        b.signal::<(u64,), _>("HotPlugDetect", ("port",));
-        b.signal::<(&str,), _>("PowerProfileSwitch", ("profile",));
+    // Methods which are authorized asynchronously reach the daemon through the crossroads.
+    let cr = Arc::new_cyclic(|weak_cr: &Weak<Mutex<Crossroads>>| {
+        let mut cr = Crossroads::new();
+        cr.set_async_support(Some((
+            c.clone(),
+            Box::new(|x| {
+                tokio::spawn(x);
+            }),
+        )));
+        let iface_token = cr.register(DBUS_IFACE, |b| {
+            authorized_method(b, weak_cr, &c, "Performance", PROFILE_POLICY, (), (), |d, _: ()| {
+                d.performance()
+            });
+            authorized_method(b, weak_cr, &c, "Balanced", PROFILE_POLICY, (), (), |d, _: ()| {
+                d.balanced()
+            });
+            authorized_method(b, weak_cr, &c, "Battery", PROFILE_POLICY, (), (), |d, _: ()| {
+                d.battery()
+            });
+            sync_get_method(
+                b,
+                "GetExternalDisplaysRequireDGPU",
+                "required",
+                PowerDaemon::get_external_displays_require_dgpu,
+            );
+            sync_get_method(b, "GetDefaultGraphics", "vendor", PowerDaemon::get_default_graphics);
+            sync_get_method(b, "GetGraphics", "vendor", PowerDaemon::get_graphics);
+            authorized_method(
+                b,
+                weak_cr,
+                &c,
+                "SetGraphics",
+                GRAPHICS_POLICY,
+                ("vendor",),
+                (),
+                |d, (s,): (String,)| d.set_graphics(&s),
+            );
+            sync_get_method(b, "GetProfile", "profile", PowerDaemon::get_profile);
+            authorized_method(
+                b,
+                weak_cr,
+                &c,
+                "SetProfile",
+                PROFILE_POLICY,
+                ("profile",),
+                (),
+                |d, (s,): (String,)| d.set_profile(&s),
+            );
+            sync_get_method(b, "GetProfiles", "profiles", PowerDaemon::get_profiles);
+            // Holds belong to the sender, so that they are released when it disconnects.
+            authorized_sender_method(
+                b,
+                weak_cr,
+                &c,
+                "HoldProfile",
+                HOLD_POLICY,
+                ("profile", "reason", "application_id"),
+                ("cookie",),
+                |d, sender, (profile, reason, id): (String, String, String)| {
+                    d.hold(Some(sender.to_owned()), &profile, &reason, &id).map(|cookie| (cookie,))
+                },
+            );
+            authorized_sender_method(
+                b,
+                weak_cr,
+                &c,
+                "ReleaseProfile",
+                HOLD_POLICY,
+                ("cookie",),
+                (),
+                |d, sender, (cookie,): (u32,)| d.release(Some(sender), cookie),
+            );
+            sync_get_method(b, "GetHolds", "holds", PowerDaemon::get_holds);
+            sync_get_method(b, "GetStartupProfile", "startup", PowerDaemon::get_startup_profile);
+            authorized_method(
+                b,
+                weak_cr,
+                &c,
+                "SetStartupProfile",
+                STARTUP_POLICY,
+                ("startup",),
+                (),
+                |d, (s,): (String,)| d.set_startup_profile(&s),
+            );
+            sync_get_method(b, "GetLastApplyReport", "report", PowerDaemon::get_last_apply_report);
+            authorized_method(
+                b,
+                weak_cr,
+                &c,
+                "RestoreDefaults",
+                RESTORE_POLICY,
+                (),
+                (),
+                |d, _: ()| d.restore_defaults(),
+            );
+            sync_get_method(b, "GetAutoSwitch", "enabled", PowerDaemon::get_auto_switch);
+            authorized_method(
+                b,
+                weak_cr,
+                &c,
+                "SetAutoSwitch",
+                AUTO_SWITCH_POLICY,
+                ("enabled",),
+                (),
+                |d, (enabled,): (bool,)| d.set_auto_switch(enabled),
+            );
+            sync_method(b, "GetAspm", (), ("policy", "links"), |d, _: ()| d.get_aspm());
+            sync_method(
+                b,
+                "DescribeProfile",
+                ("profile",),
+                ("definition",),
+                |d, (s,): (String,)| d.describe_profile(&s).map(|x| (x,)),
+            );
+            sync_method(b, "PlanProfile", ("profile",), ("operations",), |d, (s,): (String,)| {
+                d.plan_profile(&s).map(|x| (x,))
+            });
+            authorized_method(
+                b,
+                weak_cr,
+                &c,
+                "CreateProfile",
+                CREATE_PROFILE_POLICY,
+                ("profile", "definition"),
+                (),
+                |d, (name, definition): (String, String)| d.create_profile(&name, &definition),
+            );
+            sync_get_method(b, "GetSwitchable", "switchable", PowerDaemon::get_switchable);
+            sync_get_method(b, "GetGraphicsPower", "power", PowerDaemon::get_graphics_power);
+            authorized_method(
+                b,
+                weak_cr,
+                &c,
+                "SetGraphicsPower",
+                GRAPHICS_POWER_POLICY,
+                ("power",),
+                (),
+                |d, (power,): (bool,)| d.set_graphics_power(power),
+            );
+            sync_get_method(
+                b,
+                "GetChargeThresholds",
+                "thresholds",
+                PowerDaemon::get_charge_thresholds,
+            );
+            authorized_method(
+                b,
+                weak_cr,
+                &c,
+                "SetChargeThresholds",
+                THRESHOLD_POLICY,
+                ("thresholds",),
+                (),
+                |d, (thresholds,): ((u8, u8),)| d.set_charge_thresholds(thresholds),
+            );
+            sync_get_method(b, "GetChargeProfiles", "profiles", PowerDaemon::get_charge_profiles);
+            sync_get_method(b, "GetLimits", "limits", PowerDaemon::get_limits);
+            sync_get_method(b, "GetPowercap", "constraints", PowerDaemon::get_powercap);
+            sync_get_method(b, "GetThermald", "state", PowerDaemon::get_thermald);
+            authorized_method(
+                b,
+                weak_cr,
+                &c,
+                "SetLimit",
+                LIMITS_POLICY,
+                ("limit", "value"),
+                (),
+                |d, (limit, value): (String, u8)| d.set_limit(&limit, value),
+            );
+            authorized_method(b, weak_cr, &c, "ResetLimits", LIMITS_POLICY, (), (), |d, _: ()| {
+                d.reset_limits()
+            });
+            properties::register(b);
+            b.signal::<(u64,), _>("HotPlugDetect", ("port",));
+            b.signal::<(&str,), _>("PowerProfileSwitch", ("profile",));
+            b.signal::<(&str, &str), _>("PowerProfileSwitchReason", ("profile", "reason"));
+            b.signal::<(u32,), _>("ProfileReleased", ("cookie",));
+        });
+        cr.insert(DBUS_PATH, &[iface_token], daemon);
+        if power_profiles {
+            power_profiles::insert(&mut cr, weak_cr, &c);
+        }
+        Mutex::new(cr)
     });
-    cr.insert(DBUS_PATH, &[iface_token], daemon);
 
-    let cr = Arc::new(std::sync::Mutex::new(cr));
+    let daemon_cr = cr.clone();
+    let owner_cr = cr.clone();
     c.start_receive(
         MatchRule::new_method_call(),
         Box::new(move |msg, c| {
@@ -319,6 +1013,33 @@ pub async fn daemon() -> Result<(), String> {
             true
         }),
     );
+    let daemon_path = dbus::Path::from(DBUS_PATH);
+
+    // The names are only taken once the objects exist, and are left to power-profiles-daemon if
+    // it is running.
+    for &(name, _) in power_profiles::INTERFACES.iter().filter(|_| power_profiles) {
+        log::info!("Registering dbus name {}", name);
+        match c.request_name(name, false, false, true).await {
+            Ok(RequestNameReply::PrimaryOwner) => (),
+            Ok(_) => log::warn!("{} is owned by another daemon", name),
+            Err(why) => log::warn!("failed to register dbus name {}: {}", name, why),
+        }
+    }
+
+    // Releases the holds of clients which disconnect from the bus.
+    let owner_rule = MatchRule::new_signal("org.freedesktop.DBus", "NameOwnerChanged");
+    let owner_path = daemon_path.clone();
+    let _owner_changed = c.add_match(owner_rule).await.map_err(err_str)?.cb(
+        move |_, (name, _old, new): (String, String, String)| {
+            if new.is_empty() {
+                let mut cr = owner_cr.lock().unwrap();
+                if let Some(daemon) = cr.data_mut::<PowerDaemon>(&owner_path) {
+                    daemon.release_owner(&name);
+                }
+            }
+            true
+        },
+    );
 
     // Spawn hid backlight daemon
     let _hid_backlight = thread::spawn(hid_backlight::daemon);
@@ -340,11 +1061,44 @@ pub async fn daemon() -> Result<(), String> {
     let mut last = hpd();
 
     log::info!("Handling dbus requests");
+    let mut thermald_refresh = THERMALD_REFRESH_TICKS;
+    let mut properties_refresh = PROPERTIES_REFRESH_TICKS;
     while CONTINUE.load(Ordering::SeqCst) {
         sleep(Duration::from_millis(1000)).await;
 
         fan_daemon.step();
 
+        properties_refresh -= 1;
+        let all_properties = properties_refresh == 0;
+        if all_properties {
+            properties_refresh = PROPERTIES_REFRESH_TICKS;
+        }
+
+        let units = match daemon_cr.lock().unwrap().data_mut::<PowerDaemon>(&daemon_path) {
+            Some(daemon) => {
+                daemon.step_autoswitch();
+                daemon.step_apps();
+                daemon.step_properties(all_properties);
+                mem::take(&mut daemon.units)
+            }
+            None => Vec::new(),
+        };
+
+        // The state of thermald is refreshed after changing it, as well as every so often
+        // in case something else started or stopped it.
+        thermald_refresh -= 1;
+        if !units.is_empty() || thermald_refresh == 0 {
+            thermald_refresh = THERMALD_REFRESH_TICKS;
+            let results = set_units_active(&c, units).await;
+            let active = query_thermald(&c).await;
+            if let Some(daemon) = daemon_cr.lock().unwrap().data_mut::<PowerDaemon>(&daemon_path) {
+                daemon.finish_units(results);
+                if let Some(active) = active {
+                    daemon.thermald.active = active;
+                }
+            }
+        }
+
         let hpd = hpd();
         for i in 0..hpd.len() {
             if hpd[i] != last[i] && hpd[i] {
@@ -368,9 +1122,57 @@ pub async fn daemon() -> Result<(), String> {
     }
 
     log::info!("daemon exited from loop");
+
+    let units = match daemon_cr.lock().unwrap().data_mut::<PowerDaemon>(&daemon_path) {
+        Some(daemon) => {
+            if let Err(why) = daemon.restore_defaults() {
+                log::error!("{}", why);
+            }
+            mem::take(&mut daemon.units)
+        }
+        None => Vec::new(),
+    };
+
+    set_units_active(&c, units).await;
+
     Ok(())
 }
 
+/// Starts or stops the systemd units queued by the plans of the daemon, returning the failure of
+/// each, if any.
+async fn set_units_active(
+    c: &SyncConnection,
+    units: Vec<(String, bool)>,
+) -> Vec<(String, bool, Option<Failure>)> {
+    let mut results = Vec::with_capacity(units.len());
+    for (unit, active) in units {
+        let failure = match systemd::set_active(c, &unit, active).await {
+            Ok(()) => None,
+            Err(why) => {
+                let action = if active { "start" } else { "stop" };
+                log::warn!("failed to {} {}: {}", action, unit, why);
+                Some(Failure { kind: io::ErrorKind::Other, message: why.to_string() })
+            }
+        };
+
+        results.push((unit, active, failure));
+    }
+
+    results
+}
+
+/// Whether thermald is active, or `Some(None)` if it is not installed. Failures are logged,
+/// and return `None` so that the last known state is kept.
+async fn query_thermald(c: &SyncConnection) -> Option<Option<bool>> {
+    match systemd::is_active(c, thermald::UNIT).await {
+        Ok(active) => Some(active),
+        Err(why) => {
+            log::warn!("failed to query the state of thermald: {}", why);
+            None
+        }
+    }
+}
+
 fn sync_method<IA, OA, F>(
     b: &mut IfaceBuilder<PowerDaemon>,
     name: &'static str,
@@ -394,36 +1196,102 @@ fn sync_method<IA, OA, F>(
     });
 }
 
-/// DBus wrapper for a method taking no argument and returning no values
-fn sync_action_method<F>(b: &mut IfaceBuilder<PowerDaemon>, name: &'static str, f: F)
-where
-    F: Fn(&mut PowerDaemon) -> Result<(), String> + Send + 'static,
+/// Checks that the sender of a message is authorized by polkit to perform an action.
+async fn authorize(
+    c: &SyncConnection,
+    sender: BusName<'static>,
+    action_id: &str,
+) -> Result<(), MethodErr> {
+    match polkit::is_sender_authorized(c, sender, action_id).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(MethodErr::failed("Operation not permitted by Polkit")),
+        Err(why) => Err(MethodErr::failed(&why)),
+    }
+}
+
+/// Calls the daemon through the crossroads which owns it, from outside of a method handler.
+fn with_daemon<T>(
+    cr: &Weak<Mutex<Crossroads>>,
+    f: impl FnOnce(&mut PowerDaemon) -> Result<T, String>,
+) -> Result<T, MethodErr> {
+    let path = dbus::Path::from(DBUS_PATH);
+    let cr = cr.upgrade().ok_or_else(|| MethodErr::no_path(&path))?;
+    let mut cr = cr.lock().unwrap();
+    let daemon: &mut PowerDaemon = cr.data_mut(&path).ok_or_else(|| MethodErr::no_path(&path))?;
+    f(daemon).map_err(|why| MethodErr::failed(&why))
+}
+
+/// DBus wrapper for a method which the sender must be authorized to call by polkit
+#[allow(clippy::too_many_arguments)]
+fn authorized_method<T, IA, OA, F>(
+    b: &mut IfaceBuilder<T>,
+    cr: &Weak<Mutex<Crossroads>>,
+    c: &Arc<SyncConnection>,
+    name: &'static str,
+    action_id: &'static str,
+    input_args: IA::strs,
+    output_args: OA::strs,
+    f: F,
+) where
+    T: Send + 'static,
+    IA: arg::ArgAll + arg::ReadAll + Debug + Send + 'static,
+    OA: arg::ArgAll + arg::AppendAll + Send + 'static,
+    F: Fn(&mut PowerDaemon, IA) -> Result<OA, String> + Send + Sync + 'static,
 {
-    sync_method(b, name, (), (), move |d, _: ()| f(d));
+    authorized_sender_method(
+        b,
+        cr,
+        c,
+        name,
+        action_id,
+        input_args,
+        output_args,
+        move |d, _, args| f(d, args),
+    );
 }
 
-/// DBus wrapper for method taking no arguments and returning one value
-fn sync_get_method<T, F>(
-    b: &mut IfaceBuilder<PowerDaemon>,
+/// DBus wrapper for an authorized method which is also given the unique name of its sender
+#[allow(clippy::too_many_arguments)]
+fn authorized_sender_method<T, IA, OA, F>(
+    b: &mut IfaceBuilder<T>,
+    cr: &Weak<Mutex<Crossroads>>,
+    c: &Arc<SyncConnection>,
     name: &'static str,
-    output_arg: &'static str,
+    action_id: &'static str,
+    input_args: IA::strs,
+    output_args: OA::strs,
     f: F,
 ) where
-    T: arg::Arg + arg::Append + Debug,
-    F: Fn(&mut PowerDaemon) -> Result<T, String> + Send + 'static,
+    T: Send + 'static,
+    IA: arg::ArgAll + arg::ReadAll + Debug + Send + 'static,
+    OA: arg::ArgAll + arg::AppendAll + Send + 'static,
+    F: Fn(&mut PowerDaemon, &str, IA) -> Result<OA, String> + Send + Sync + 'static,
 {
-    sync_method(b, name, (), (output_arg,), move |d, _: ()| f(d).map(|x| (x,)));
+    let (cr, c, f) = (cr.clone(), c.clone(), Arc::new(f));
+    b.method_with_cr_async(name, input_args, output_args, move |mut ctx, _cr, args: IA| {
+        log::info!("DBUS Received {}{:?} method", name, args);
+        let sender = ctx.message().sender().map(|sender| sender.into_static());
+        let (cr, c, f) = (cr.clone(), c.clone(), f.clone());
+        async move {
+            let res = async {
+                let sender = sender.ok_or_else(|| MethodErr::failed("Message has no sender"))?;
+                authorize(&c, sender.clone(), action_id).await?;
+                with_daemon(&cr, |daemon| f(daemon, &sender, args))
+            };
+            ctx.reply(res.await)
+        }
+    });
 }
 
-/// DBus wrapper for method taking one argument and returning no values
-fn sync_set_method<T, F>(
+/// DBus wrapper for method taking no arguments and returning one value
+fn sync_get_method<T, F>(
     b: &mut IfaceBuilder<PowerDaemon>,
     name: &'static str,
-    input_arg: &'static str,
+    output_arg: &'static str,
     f: F,
 ) where
-    T: arg::Arg + for<'z> arg::Get<'z> + Debug,
-    F: Fn(&mut PowerDaemon, T) -> Result<(), String> + Send + 'static,
+    T: arg::Arg + arg::Append + Debug,
+    F: Fn(&mut PowerDaemon) -> Result<T, String> + Send + 'static,
 {
-    sync_method(b, name, (input_arg,), (), move |d, (arg,)| f(d, arg))
+    sync_method(b, name, (), (output_arg,), move |d, _: ()| f(d).map(|x| (x,)));
 }
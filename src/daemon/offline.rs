@@ -0,0 +1,127 @@
+// Copyright 2022 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! CPUs taken offline by a profile, by disabling SMT or by CPU hotplug.
+//!
+//! Only what the daemon took offline is brought back online, once a profile which keeps it online
+//! is applied. The snapshot of the daemon brings it back as well when the original values are
+//! restored, such as on shutdown.
+
+use super::config::CpuConfig;
+use crate::{cpus, errors::CpuError, plan::Plan};
+use std::collections::BTreeSet;
+
+/// What the daemon took offline, kept by the daemon.
+#[derive(Debug, Default)]
+pub struct Offline {
+    /// Whether SMT was disabled by the daemon.
+    smt_disabled: bool,
+    /// The CPUs which were taken offline by the daemon.
+    cpus:         BTreeSet<usize>,
+}
+
+impl Offline {
+    /// Takes the CPUs of a profile offline, and brings those which the daemon took offline for a
+    /// previous profile back online.
+    pub fn set(&mut self, plan: &mut Plan, config: &CpuConfig) -> Result<(), CpuError> {
+        let mut result = Ok(());
+
+        // CPUs are brought back before SMT, and taken offline after it, as is done by the kernel.
+        for cpu in self.cpus.clone() {
+            if config.offline.contains(&cpu) {
+                continue;
+            }
+
+            if cpus::is_online(cpu) == Some(false) {
+                log::info!("bringing CPU {} back online", cpu);
+                if let Err(why) = cpus::set_online(plan, cpu, true) {
+                    result = Err(why);
+                    continue;
+                }
+            }
+
+            if !plan.is_dry_run() {
+                self.cpus.remove(&cpu);
+            }
+        }
+
+        let smt_on = cpus::smt_control().map_or(false, |control| control == "on");
+        if config.disable_smt && smt_on {
+            log::info!("disabling SMT");
+            match cpus::set_smt(plan, false) {
+                Ok(()) if !plan.is_dry_run() => self.smt_disabled = true,
+                Ok(()) => (),
+                Err(why) => result = Err(why),
+            }
+        } else if !config.disable_smt && self.smt_disabled {
+            if cpus::smt_control().map_or(false, |control| control == "off") {
+                log::info!("enabling SMT again");
+                if let Err(why) = cpus::set_smt(plan, true) {
+                    result = Err(why);
+                }
+            }
+
+            if !plan.is_dry_run() {
+                self.smt_disabled = false;
+            }
+        }
+
+        for &cpu in &config.offline {
+            if cpus::is_online(cpu) != Some(false) {
+                log::info!("taking CPU {} offline", cpu);
+                if let Err(why) = cpus::set_online(plan, cpu, false) {
+                    result = Err(why);
+                    continue;
+                }
+
+                if !plan.is_dry_run() {
+                    self.cpus.insert(cpu);
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Forgets what was taken offline, once the snapshot has brought it back online.
+    pub fn restored(&mut self) {
+        self.smt_disabled = false;
+        self.cpus.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysroot::FakeRoot;
+
+    #[test]
+    fn offlined_cpus_are_brought_back() {
+        let root = FakeRoot::new("offline");
+        root.write(cpus::SMT_CONTROL_PATH, "on\n");
+        for cpu in 1..4 {
+            root.write(&format!("/sys/devices/system/cpu/cpu{}/online", cpu), "1\n");
+        }
+
+        let mut offline = Offline::default();
+        let battery = CpuConfig { disable_smt: true, offline: vec![2, 3] };
+        offline.set(&mut Plan::new(), &battery).unwrap();
+        assert_eq!(root.read(cpus::SMT_CONTROL_PATH), "off");
+        assert_eq!(cpus::is_online(1), Some(true));
+        assert_eq!(cpus::is_online(3), Some(false));
+
+        let online = CpuConfig { disable_smt: false, offline: vec![0] };
+        assert!(matches!(
+            offline.set(&mut Plan::new(), &online),
+            Err(CpuError::NotHotpluggable(0))
+        ));
+        assert_eq!(root.read(cpus::SMT_CONTROL_PATH), "on");
+        assert_eq!(cpus::is_online(2), Some(true));
+        assert_eq!(cpus::is_online(3), Some(true));
+
+        offline.set(&mut Plan::new(), &CpuConfig::default()).unwrap();
+        assert!(offline.cpus.is_empty());
+        assert!(!offline.smt_disabled);
+    }
+}
@@ -0,0 +1,208 @@
+// Copyright 2022 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! The interface of power-profiles-daemon, through which desktops such as GNOME and KDE select
+//! profiles.
+//!
+//! It is exported under the `net.hadess.PowerProfiles` name and its newer
+//! `org.freedesktop.UPower.PowerProfiles` name when enabled in the daemon settings. Its
+//! `power-saver`, `balanced` and `performance` profiles are the battery, balanced and performance
+//! profiles, and custom profiles are reported as the profile they are based on. Switching and
+//! holding profiles through it are authorized by polkit like the methods of the daemon.
+
+use super::{
+    authorize, authorized_sender_method, with_daemon, PowerDaemon, HOLD_POLICY, PROFILE_POLICY,
+};
+use crate::{Power, Profile, DBUS_PATH};
+use dbus::{
+    arg::{RefArg, Variant},
+    blocking::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged,
+    message::SignalArgs,
+    nonblock::SyncConnection,
+    Message,
+};
+use dbus_crossroads::{Crossroads, IfaceBuilder, MethodErr};
+use std::{
+    collections::HashMap,
+    marker::PhantomData,
+    sync::{Arc, Mutex, Weak},
+};
+
+/// The name of the interface, and the path of its object, under each bus name.
+pub const INTERFACES: [(&str, &str); 2] = [
+    ("net.hadess.PowerProfiles", "/net/hadess/PowerProfiles"),
+    ("org.freedesktop.UPower.PowerProfiles", "/org/freedesktop/UPower/PowerProfiles"),
+];
+
+const DRIVER: &str = "system76-power";
+
+type Dict = HashMap<String, Variant<String>>;
+
+fn name(profile: Profile) -> &'static str {
+    match profile {
+        Profile::Battery => "power-saver",
+        Profile::Balanced => "balanced",
+        Profile::Performance => "performance",
+    }
+}
+
+fn from_name(name: &str) -> Option<Profile> {
+    Profile::BUILTIN.iter().copied().find(|&profile| self::name(profile) == name)
+}
+
+fn dict(entries: &[(&str, &str)]) -> Dict {
+    entries.iter().map(|&(key, value)| (key.to_owned(), Variant(value.to_owned()))).collect()
+}
+
+fn daemon(cr: &mut Crossroads) -> Result<&mut PowerDaemon, MethodErr> {
+    let path = dbus::Path::from(DBUS_PATH);
+    cr.data_mut(&path).ok_or_else(|| MethodErr::no_path(&path))
+}
+
+/// The active profile, where a custom profile is the built-in profile it is based on.
+fn active_profile(daemon: &PowerDaemon) -> &'static str {
+    let name = &daemon.power_profile;
+    let profile = Profile::from_id(name).or_else(|| daemon.load_profile(name).ok()?.base);
+    profile.map_or("balanced", self::name)
+}
+
+fn active_profile_holds(daemon: &PowerDaemon) -> Vec<Dict> {
+    daemon
+        .holds
+        .entries()
+        .into_iter()
+        .map(|(_cookie, profile, reason, application_id)| {
+            let profile = Profile::from_id(&profile).map_or("", name);
+            dict(&[("ApplicationId", &application_id), ("Profile", profile), ("Reason", &reason)])
+        })
+        .collect()
+}
+
+/// Registers the interface under each of its names, with an object at each of their paths.
+pub fn insert(cr: &mut Crossroads, weak_cr: &Weak<Mutex<Crossroads>>, c: &Arc<SyncConnection>) {
+    for &(interface, path) in &INTERFACES {
+        let token = cr.register(interface, |b: &mut IfaceBuilder<()>| {
+            let (set_cr, set_c) = (weak_cr.clone(), c.clone());
+            b.property("ActiveProfile")
+                .get_with_cr(|_, cr| Ok(active_profile(daemon(cr)?).to_owned()))
+                .set_with_cr_async(move |mut ctx, _, profile: String| {
+                    log::info!("DBUS Received ActiveProfile = {} property", profile);
+                    let sender = ctx.message().and_then(|m| m.sender()).map(|s| s.into_static());
+                    let (cr, c) = (set_cr.clone(), set_c.clone());
+                    async move {
+                        let res = async {
+                            let profile = from_name(&profile).ok_or_else(|| {
+                                MethodErr::invalid_arg(&format!("unknown profile '{}'", profile))
+                            })?;
+                            let sender =
+                                sender.ok_or_else(|| MethodErr::failed("Message has no sender"))?;
+                            authorize(&c, sender, PROFILE_POLICY).await?;
+
+                            // The change is signalled once the profile is applied.
+                            with_daemon(&cr, |daemon| daemon.set_profile(profile.id()))
+                        };
+                        ctx.reply_noemit(res.await);
+                        PhantomData
+                    }
+                });
+            b.property("PerformanceDegraded").get(|_, _| Ok(String::new()));
+            b.property("PerformanceInhibited").get(|_, _| Ok(String::new())).deprecated();
+            b.property("Profiles")
+                .get(|_, _| {
+                    Ok(Profile::BUILTIN
+                        .iter()
+                        .map(|&profile| {
+                            dict(&[
+                                ("Profile", name(profile)),
+                                ("Driver", DRIVER),
+                                ("PlatformDriver", DRIVER),
+                            ])
+                        })
+                        .collect::<Vec<_>>())
+                })
+                .emits_changed_const();
+            b.property("Actions").get(|_, _| Ok(Vec::<String>::new())).emits_changed_const();
+            b.property("ActiveProfileHolds")
+                .get_with_cr(|_, cr| Ok(active_profile_holds(daemon(cr)?)));
+            b.property("Version")
+                .get(|_, _| Ok(env!("CARGO_PKG_VERSION").to_owned()))
+                .emits_changed_const();
+            authorized_sender_method(
+                b,
+                weak_cr,
+                c,
+                "HoldProfile",
+                HOLD_POLICY,
+                ("profile", "reason", "application_id"),
+                ("cookie",),
+                |daemon, sender, (profile, reason, id): (String, String, String)| {
+                    let held = from_name(&profile)
+                        .filter(|&profile| profile != Profile::Balanced)
+                        .ok_or_else(|| format!("cannot hold the '{}' profile", profile))?;
+                    let owner = Some(sender.to_owned());
+                    daemon.hold(owner, held.id(), &reason, &id).map(|cookie| (cookie,))
+                },
+            );
+            authorized_sender_method(
+                b,
+                weak_cr,
+                c,
+                "ReleaseProfile",
+                HOLD_POLICY,
+                ("cookie",),
+                (),
+                |daemon, sender, (cookie,): (u32,)| daemon.release(Some(sender), cookie),
+            );
+            b.signal::<(u32,), _>("ProfileReleased", ("cookie",));
+        });
+
+        cr.insert(path, &[token], ());
+    }
+}
+
+/// Signals that the active profile and its holds may have changed.
+pub fn properties_changed(daemon: &PowerDaemon) -> Vec<Message> {
+    let mut changed: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+    changed.insert("ActiveProfile".into(), Variant(Box::new(active_profile(daemon).to_owned())));
+    changed.insert("ActiveProfileHolds".into(), Variant(Box::new(active_profile_holds(daemon))));
+
+    INTERFACES
+        .iter()
+        .map(|&(interface, path)| {
+            PropertiesPropertiesChanged {
+                interface_name:         interface.to_owned(),
+                changed_properties:     changed
+                    .iter()
+                    .map(|(name, value)| (name.clone(), Variant(value.0.box_clone())))
+                    .collect(),
+                invalidated_properties: Vec::new(),
+            }
+            .to_emit_message(&path.into())
+        })
+        .collect()
+}
+
+/// Signals that a hold was released other than by the client which took it.
+pub fn profile_released(cookie: u32) -> Vec<Message> {
+    INTERFACES
+        .iter()
+        .map(|&(interface, path)| {
+            Message::new_signal(path, interface, "ProfileReleased").unwrap().append1(cookie)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_map_to_builtin_profiles() {
+        assert_eq!(from_name("power-saver"), Some(Profile::Battery));
+        assert_eq!(from_name("battery"), None);
+        for &profile in Profile::BUILTIN.iter() {
+            assert_eq!(from_name(name(profile)), Some(profile));
+        }
+    }
+}
@@ -2,24 +2,37 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
-use super::pci_runtime_pm_support;
+use super::{
+    config::{PStateConfig, ProfileConfig},
+    offline::Offline,
+    pci_runtime_pm_support,
+    thermald::Thermald,
+};
 use crate::{
-    errors::{BacklightError, ModelError, PciDeviceError, ProfileError, ScsiHostError},
+    aspm,
+    cpufreq::MaxPercent,
+    disks::{DiskPower, Disks},
+    errors::{
+        BacklightError, ModelError, PciDeviceError, PowercapError, ProfileError, ScsiHostError,
+    },
+    hardware::{self, ModelProfile},
     kernel_parameters::{DeviceList, Dirty, KernelParameter, LaptopMode},
+    plan::{self, Plan},
+    powercap::{self, ConstraintLimit, Zone},
     radeon::RadeonDevice,
-    Profile,
-};
-use intel_pstate::{PState, PStateError, PStateValues};
-use std::{
-    fs,
-    io::{self, Read, Seek, SeekFrom, Write},
-    path::Path,
-    process::Command,
+    snd::SoundDevice,
+    sysroot, Profile,
 };
+use std::{fs, io};
 use sysfs_class::{
-    Backlight, Brightness, Leds, PciDevice, RuntimePM, RuntimePowerManagement, ScsiHost, SysClass,
+    Backlight, Brightness, Leds, PciDevice, RuntimePowerManagement, ScsiHost, SysClass,
 };
 
+const INTEL_PSTATE_PATH: &str = "/sys/devices/system/cpu/intel_pstate";
+
+const MSR_PATH: &str = "/dev/cpu/0/msr";
+const MSR_TEMPERATURE_TARGET: u64 = 0x1A2;
+
 /// Instead of returning on the first error, we want to collect all errors that occur while
 /// setting a profile. Even if one parameter fails to set, we'll still be able to set other
 /// parameters successfully.
@@ -32,151 +45,224 @@ macro_rules! catch {
     };
 }
 
-/// Sets parameters for the balanced profile.
-pub fn balanced(errors: &mut Vec<ProfileError>, set_brightness: bool) {
+/// Sets the parameters described by a profile definition, through the given plan. The custom
+/// power limits take the place of those of the model, and thermald is stopped or started
+/// depending on whether the profile manages the power limits. `thermald` and `offline` keep what
+/// the daemon changed for previous profiles, so that only that is undone.
+pub fn apply(
+    plan: &mut Plan,
+    config: &ProfileConfig,
+    custom_limits: &ModelProfile,
+    thermald: &mut Thermald,
+    offline: &mut Offline,
+    errors: &mut Vec<ProfileError>,
+    set_brightness: bool,
+) {
     // Use the ACPI Platform Profile if the hardware is supported by the kernel.
     if crate::acpi_platform::supported() {
-        crate::acpi_platform::balanced();
+        plan.setting("acpi_platform_profile");
+        crate::acpi_platform::set(plan, &config.acpi_platform_profile);
         return;
     }
 
-    // The dirty kernel parameter controls how often the OS will sync data to disks. The less
-    // frequently this occurs, the more power can be saved, yet the higher the risk of sudden
-    // power loss causing loss of data. 15s is a resonable number.
-    Dirty::default().set_max_lost_work(15);
+    plan.setting("max_lost_work");
+    Dirty::default().set_max_lost_work(plan, config.max_lost_work);
 
-    // Enables the laptop mode feature in the kernel, which allows mechanical drives to spin down
-    // when inactive.
-    LaptopMode::default().set(b"2");
+    // Controls whether mechanical drives may spin down when inactive.
+    plan.setting("laptop_mode");
+    LaptopMode::default().set_with(plan, &config.laptop_mode.to_string());
 
     // Sets radeon power profiles for AMD graphics.
-    RadeonDevice::get_devices().for_each(|dev| dev.set_profiles("auto", "performance", "auto"));
+    plan.setting("radeon");
+    let radeon = &config.radeon;
+    RadeonDevice::get_devices().for_each(|dev| {
+        dev.set_profiles(plan, &radeon.power_profile, &radeon.dpm_state, &radeon.dpm_perf)
+    });
+
+    // Sets SCSI / SATA link time power management.
+    plan.setting("scsi_link_policies");
+    catch!(errors, scsi_host_link_time_pm_policy(plan, &config.scsi_link_policies));
+
+    // Sets the APM level and autosuspend delay of each rotational disk.
+    let disks = Disks::default();
+    plan.setting("disks.apm_level");
+    if let Some(level) = config.disks.apm_level {
+        for disk in disks.rotational() {
+            catch!(errors, disk.set_apm_level(plan, level));
+        }
+    }
 
-    // Enables SCSI / SATA link time power management.
-    catch!(errors, scsi_host_link_time_pm_policy(&["med_power_with_dipm", "medium_power"]));
+    plan.setting("disks.autosuspend_delay_ms");
+    if let Some(ms) = config.disks.autosuspend_delay_ms {
+        for disk in disks.rotational() {
+            catch!(errors, disk.set_autosuspend_delay(plan, ms));
+        }
+    }
+
+    // Powers down idle audio codecs.
+    plan.setting("audio");
+    SoundDevice::get_devices().for_each(|dev| {
+        dev.set_power_save(plan, config.audio.power_save, config.audio.power_save_controller)
+    });
+
+    // Sets the power save mode of wireless interfaces.
+    plan.setting("wifi");
+    if let Some(enabled) = config.wifi.power_save {
+        catch!(errors, crate::wifi::set_power_save(plan, enabled, config.wifi.power_level));
+    }
 
     if set_brightness {
         // Manage screen backlights.
-        catch!(errors, iterate_backlights(Backlight::iter(), &Brightness::set_if_lower_than, 40));
+        plan.setting("backlight.screen");
+        if let Some(percent) = config.backlight.screen {
+            catch!(errors, iterate_backlights(plan, sysroot::class_iter::<Backlight>(), percent));
+        }
 
         // Manage keyboard backlights.
-        catch!(
-            errors,
-            iterate_backlights(Leds::iter_keyboards(), &Brightness::set_if_lower_than, 50)
-        );
+        plan.setting("backlight.keyboard");
+        if let Some(percent) = config.backlight.keyboard {
+            catch!(errors, iterate_backlights(plan, keyboard_backlights(), percent));
+        }
     }
 
     // Parameters which may cause on certain systems.
     if pci_runtime_pm_support() {
-        // Enables PCI device runtime power management.
-        catch!(errors, pci_device_runtime_pm(RuntimePowerManagement::On));
+        plan.setting("pci_runtime_pm");
+        let pm = if config.pci_runtime_pm {
+            RuntimePowerManagement::On
+        } else {
+            RuntimePowerManagement::Off
+        };
+
+        catch!(errors, pci_device_runtime_pm(plan, pm));
     }
 
-    // Set to balanced profile.
-    crate::cpufreq::set(Profile::Balanced, 100);
+    // Sets PCIe Active State Power Management.
+    plan.setting("aspm.policy");
+    if let Some(ref policy) = config.aspm.policy {
+        catch!(errors, aspm::set_policy(plan, policy));
+    }
 
-    // Control Intel PState values, if they exist.
-    catch!(
-        errors,
-        pstate_values(
-            PStateValues::default()
-                .hwp_dynamic_boost(true)
-                .min_perf_pct(0)
-                .max_perf_pct(100)
-                .no_turbo(false)
-        )
-    );
-
-    if let Some(model_profiles) = ModelProfiles::new() {
-        catch!(errors, model_profiles.balanced.set());
+    plan.setting("aspm.link_states");
+    catch!(errors, aspm::set_link_states(plan, &config.aspm.link_states, &config.aspm.devices));
+
+    // Cores are brought back online before their frequencies are set.
+    plan.setting("cpus");
+    catch!(errors, offline.set(plan, &config.cpus));
+
+    plan.setting("cpufreq");
+    let cpufreq = &config.cpufreq;
+    let max_percent = MaxPercent {
+        all:     cpufreq.max_percent,
+        p_cores: cpufreq.p_core_max_percent,
+        e_cores: cpufreq.e_core_max_percent,
+    };
+    crate::cpufreq::set(plan, &cpufreq.pstate_governor, &cpufreq.governor, max_percent);
+
+    // The preference is set after the governor, which may replace it.
+    plan.setting("cpufreq.energy_performance_preference");
+    crate::cpufreq::set_energy_performance_preference(plan, &cpufreq.energy_performance_preference);
+
+    if let Some(bias) = cpufreq.energy_perf_bias {
+        plan.setting("cpufreq.energy_perf_bias");
+        crate::cpufreq::set_energy_perf_bias(plan, bias);
     }
-}
 
-/// Sets parameters for the performance profile
-pub fn performance(errors: &mut Vec<ProfileError>, _set_brightness: bool) {
-    // Use the ACPI Platform Profile if the hardware is supported by the kernel.
-    if crate::acpi_platform::supported() {
-        crate::acpi_platform::performance();
-        return;
+    if let Some(boost) = cpufreq.boost {
+        plan.setting("cpufreq.boost");
+        crate::cpufreq::set_boost(plan, boost);
     }
 
-    Dirty::default().set_max_lost_work(15);
-    LaptopMode::default().set(b"0");
-    RadeonDevice::get_devices().for_each(|dev| dev.set_profiles("high", "performance", "auto"));
-    catch!(errors, scsi_host_link_time_pm_policy(&["med_power_with_dipm", "max_performance"]));
-    crate::cpufreq::set(Profile::Performance, 100);
-    catch!(
-        errors,
-        pstate_values(
-            PStateValues::default()
-                .hwp_dynamic_boost(true)
-                .min_perf_pct(0)
-                .max_perf_pct(100)
-                .no_turbo(false)
-        )
-    );
+    // Control Intel PState values, if they exist.
+    plan.setting("pstate");
+    catch!(errors, pstate_values(plan, &config.pstate));
 
-    if pci_runtime_pm_support() {
-        catch!(errors, pci_device_runtime_pm(RuntimePowerManagement::Off));
-    }
+    let limits = model_limits(config.model_limits, custom_limits);
+    plan.setting("thermald");
+    let manages_limits = !config.powercap.is_empty() || limits != ModelProfile::default();
+    coordinate_thermald(plan, thermald, manages_limits);
 
-    if let Some(model_profiles) = ModelProfiles::new() {
-        catch!(errors, model_profiles.performance.set());
+    plan.setting("powercap");
+    catch!(errors, powercap::set(plan, &config.powercap));
+
+    if limits != ModelProfile::default() {
+        plan.setting("model_limits");
+        catch!(errors, limits.set(plan));
     }
 }
 
-/// Sets parameters for the battery profile
-pub fn battery(errors: &mut Vec<ProfileError>, set_brightness: bool) {
-    // Use the ACPI Platform Profile if the hardware is supported by the kernel.
-    if crate::acpi_platform::supported() {
-        crate::acpi_platform::battery();
-        return;
+/// Stops thermald, which sets the power limits on its own, when they are managed by a profile, or
+/// else starts it again if it was stopped.
+pub fn coordinate_thermald(plan: &mut Plan, thermald: &mut Thermald, manages_limits: bool) {
+    if manages_limits {
+        thermald.stop(plan);
+    } else {
+        thermald.restore(plan);
     }
+}
 
-    Dirty::default().set_max_lost_work(15);
-    LaptopMode::default().set(b"2");
-    RadeonDevice::get_devices().for_each(|dev| dev.set_profiles("low", "battery", "low"));
-    catch!(errors, scsi_host_link_time_pm_policy(&["min_power", "min_power"]));
-    crate::cpufreq::set(Profile::Battery, 50);
+/// The power limits of the model for a profile, with the custom limits taking their place.
+pub fn model_limits(profile: Option<Profile>, custom_limits: &ModelProfile) -> ModelProfile {
+    profile
+        .and_then(|profile| Some(hardware::current_model()?.limits?.get(profile).clone()))
+        .unwrap_or_default()
+        .overridden_by(custom_limits)
+}
 
-    catch!(
-        errors,
-        pstate_values(PStateValues::default().min_perf_pct(0).max_perf_pct(50).no_turbo(true))
-    );
+/// Iterates the keyboard backlights found under the root directory.
+fn keyboard_backlights() -> impl Iterator<Item = io::Result<Leds>> {
+    sysroot::class_iter::<Leds>().filter(|led| match led {
+        Ok(led) => led.id().contains("kbd_backlight"),
+        Err(_) => true,
+    })
+}
 
-    if set_brightness {
-        catch!(errors, iterate_backlights(Backlight::iter(), &Brightness::set_if_lower_than, 10));
-        catch!(errors, iterate_backlights(Leds::iter_keyboards(), &Brightness::set_brightness, 0));
+/// Controls the Intel PState values.
+fn pstate_values(plan: &mut Plan, pstate: &PStateConfig) -> Result<(), ProfileError> {
+    let dir = sysroot::path(INTEL_PSTATE_PATH);
+    if !dir.exists() {
+        return Ok(());
     }
 
-    if pci_runtime_pm_support() {
-        catch!(errors, pci_device_runtime_pm(RuntimePowerManagement::On));
-    }
+    let mut write = |file: &str, value: u8| {
+        let path = dir.join(file);
+        plan.write(&path, value).map_err(|why| ProfileError::Write(path, why))
+    };
 
-    if let Some(model_profiles) = ModelProfiles::new() {
-        catch!(errors, model_profiles.battery.set());
+    // The minimum may not exceed the maximum, so lower the maximum after raising the minimum.
+    let current_max = fs::read_to_string(dir.join("max_perf_pct"))
+        .ok()
+        .and_then(|value| value.trim().parse::<u8>().ok())
+        .unwrap_or(100);
+    if pstate.min_perf_pct > current_max {
+        write("max_perf_pct", pstate.max_perf_pct)?;
+        write("min_perf_pct", pstate.min_perf_pct)?;
+    } else {
+        write("min_perf_pct", pstate.min_perf_pct)?;
+        write("max_perf_pct", pstate.max_perf_pct)?;
     }
-}
 
-/// Controls the Intel PState values.
-fn pstate_values(values: PStateValues) -> Result<(), PStateError> {
-    if let Ok(pstate) = PState::new() {
-        pstate.set_values(values)?;
+    write("no_turbo", pstate.no_turbo as u8)?;
+
+    if let Some(boost) = pstate.hwp_dynamic_boost {
+        if dir.join("hwp_dynamic_boost").exists() {
+            write("hwp_dynamic_boost", boost as u8)?;
+        }
     }
 
     Ok(())
 }
 
-/// Iterates across all backlights in the supplied iterator, executing the given strategy function
-/// on each discovered backlight source.
+/// Iterates across all backlights in the supplied iterator, lowering each discovered backlight
+/// source to the given percentage of its maximum brightness if it is brighter.
 fn iterate_backlights<B: Brightness>(
+    plan: &mut Plan,
     iterator: impl Iterator<Item = io::Result<B>>,
-    strategy: &dyn Fn(&B, u64) -> io::Result<()>,
-    value: u64,
+    percent: u64,
 ) -> Result<(), BacklightError> {
     for backlight in iterator {
         match backlight {
-            Ok(ref backlight) => set_backlight(strategy, backlight, value)?,
+            Ok(ref backlight) => set_backlight_if_lower_than(plan, backlight, percent)?,
             Err(why) => {
                 log::warn!("failed to iterate keyboard backlight: {}", why);
             }
@@ -187,11 +273,20 @@ fn iterate_backlights<B: Brightness>(
 }
 
 /// Iterates on all available PCI devices, disabling or enabling runtime power mangement.
-fn pci_device_runtime_pm(pm: RuntimePowerManagement) -> Result<(), PciDeviceError> {
-    for device in PciDevice::iter() {
+fn pci_device_runtime_pm(
+    plan: &mut Plan,
+    pm: RuntimePowerManagement,
+) -> Result<(), PciDeviceError> {
+    // Runtime power management is enabled by letting the kernel control the device.
+    let control = match pm {
+        RuntimePowerManagement::On => "auto",
+        RuntimePowerManagement::Off => "on",
+    };
+
+    for device in sysroot::class_iter::<PciDevice>() {
         match device {
-            Ok(device) => device
-                .set_runtime_pm(pm)
+            Ok(device) => plan
+                .write(device.path().join("power/control"), control)
                 .map_err(|why| PciDeviceError::SetRuntimePm(device.id().to_owned(), why))?,
             Err(why) => {
                 log::warn!("failed to iterate PCI device: {}", why);
@@ -204,12 +299,28 @@ fn pci_device_runtime_pm(pm: RuntimePowerManagement) -> Result<(), PciDeviceErro
 
 /// Iterates on all available SCSI/SATA hosts, setting the first link time power mangement policy
 /// that succeeeds.
-fn scsi_host_link_time_pm_policy(policies: &'static [&'static str]) -> Result<(), ScsiHostError> {
-    for device in ScsiHost::iter() {
+fn scsi_host_link_time_pm_policy(
+    plan: &mut Plan,
+    policies: &[String],
+) -> Result<(), ScsiHostError> {
+    for device in sysroot::class_iter::<ScsiHost>() {
         match device {
             Ok(device) => {
-                device.set_link_power_management_policy(policies).map_err(|why| {
-                    ScsiHostError::LinkTimePolicy(policies[0], device.id().to_owned(), why)
+                let path = device.path().join("link_power_management_policy");
+                let mut result = Ok(());
+                for policy in policies {
+                    result = plan.write(&path, policy);
+                    if result.is_ok() {
+                        break;
+                    }
+                }
+
+                result.map_err(|why| {
+                    ScsiHostError::LinkTimePolicy(
+                        policies.first().cloned().unwrap_or_default(),
+                        device.id().to_owned(),
+                        why,
+                    )
                 })?;
             }
             Err(why) => {
@@ -221,126 +332,226 @@ fn scsi_host_link_time_pm_policy(policies: &'static [&'static str]) -> Result<()
     Ok(())
 }
 
-/// Generically sets a backlight value to the backlight, using the provided strategy function.
-fn set_backlight<B: Brightness>(
-    strategy: impl Fn(&B, u64) -> io::Result<()>,
+/// Sets the brightness of a backlight to a percentage of its maximum, if that is lower than its
+/// current brightness.
+fn set_backlight_if_lower_than<B: Brightness>(
+    plan: &mut Plan,
     backlight: &B,
-    value: u64,
+    percent: u64,
 ) -> Result<(), BacklightError> {
-    strategy(backlight, value)
-        .map_err(|why| BacklightError::Set(backlight.id().to_owned(), why))?;
-    Ok(())
-}
+    let set_error = |why| BacklightError::Set(backlight.id().to_owned(), why);
+    let max_brightness = backlight.max_brightness().map_err(set_error)?;
+    let current = backlight.brightness().map_err(set_error)?;
+    let new = max_brightness * percent / 100;
+    if new < current {
+        plan.write(backlight.path().join("brightness"), new).map_err(set_error)?;
+    }
 
-pub struct ModelProfile {
-    pl1:        Option<u8>,
-    pl2:        Option<u8>,
-    tcc_offset: Option<u8>,
+    Ok(())
 }
 
 impl ModelProfile {
-    // TODO pub fn get() -> Result<Self, ModelError> {}
-
-    pub fn set(&self) -> Result<(), ModelError> {
-        // Thermald sets pl1 and pl2 on its own, conflicting with system76-power
-        let _status = Command::new("systemctl")
-            .arg("stop")
-            .arg("thermald.service")
-            .status()
-            .map_err(ModelError::Thermald)?;
-        // TODO: check status, allow thermald to be missing
-
-        // Set PL1
-        if let Some(pl1) = self.pl1 {
-            fs::write(
-                "/sys/class/powercap/intel-rapl:0/constraint_0_power_limit_uw",
-                format!("{}", (pl1 as u64) * 1_000_000),
-            )
-            .map_err(ModelError::Pl1)?;
-        }
+    /// Reads the limits which are in effect. Limits which the hardware does not have, or which
+    /// cannot be read without loading the msr module, are omitted.
+    pub fn get() -> Result<Self, ModelError> {
+        let packages = package_zones()?;
+
+        // The limits of a package may be set through both its MSR and MMIO zones, and the
+        // lowest one is enforced.
+        let limit = |constraint: &str| {
+            packages
+                .iter()
+                .filter_map(|zone| zone.constraint(constraint)?.power_limit_uw)
+                .min()
+                .map(|microwatts| ((microwatts + 500_000) / 1_000_000).min(u8::MAX.into()) as u8)
+        };
+
+        // AMD CPUs, and Intel CPUs without the register, fail to read it with EIO.
+        let path = sysroot::path(MSR_PATH);
+        let tcc_offset = match plan::read_register(&path, MSR_TEMPERATURE_TARGET) {
+            Ok(register) => Some(tcc_offset(register)),
+            Err(why) => {
+                if why.kind() != io::ErrorKind::NotFound {
+                    log::debug!("failed to read TCC offset from {}: {}", path.display(), why);
+                }
+                None
+            }
+        };
+
+        Ok(Self {
+            pl1: limit(powercap::LONG_TERM),
+            pl2: limit(powercap::SHORT_TERM),
+            pl4: limit(powercap::PEAK_POWER),
+            tcc_offset,
+        })
+    }
 
-        // Set PL2
-        if let Some(pl2) = self.pl2 {
-            fs::write(
-                "/sys/class/powercap/intel-rapl:0/constraint_1_power_limit_uw",
-                format!("{}", (pl2 as u64) * 1_000_000),
-            )
-            .map_err(ModelError::Pl2)?;
+    pub fn set(&self, plan: &mut Plan) -> Result<(), ModelError> {
+        // Set PL1, PL2 and PL4 on every package which has them.
+        let limits = [
+            (powercap::LONG_TERM, self.pl1),
+            (powercap::SHORT_TERM, self.pl2),
+            (powercap::PEAK_POWER, self.pl4),
+        ];
+        if limits.iter().any(|(_, watts)| watts.is_some()) {
+            let packages = package_zones()?;
+            for &(constraint, watts) in &limits {
+                let limit = match watts {
+                    Some(watts) => {
+                        ConstraintLimit { power_limit_w: Some(watts.into()), ..Default::default() }
+                    }
+                    None => continue,
+                };
+
+                let zones = packages.iter().filter(|zone| zone.constraint(constraint).is_some());
+                let mut found = false;
+                for zone in zones {
+                    zone.set(plan, constraint, &limit)?;
+                    found = true;
+                }
+
+                if !found {
+                    return Err(ModelError::Powercap(PowercapError::NoConstraint {
+                        zone:       "package".into(),
+                        constraint: constraint.into(),
+                    }));
+                }
+            }
         }
 
         // Set TCC
         if let Some(tcc_offset) = self.tcc_offset {
-            let path = Path::new("/dev/cpu/0/msr");
+            let path = sysroot::path(MSR_PATH);
             if !path.is_file() {
-                let status =
-                    Command::new("modprobe").arg("msr").status().map_err(ModelError::ModprobeIo)?;
+                let status = plan.run("modprobe", &["msr"]).map_err(ModelError::ModprobeIo)?;
                 if !status.success() {
                     return Err(ModelError::ModprobeExitStatus(status));
                 }
             }
 
-            let mut file = fs::OpenOptions::new()
-                .read(true)
-                .write(true)
-                .open(path)
-                .map_err(ModelError::MsrOpen)?;
-            file.seek(SeekFrom::Start(0x1A2)).map_err(ModelError::MsrSeek)?;
-            let mut data = [0; 8];
-            file.read_exact(&mut data).map_err(ModelError::MsrRead)?;
-            data[3] = tcc_offset;
-            file.write_all(&data).map_err(ModelError::MsrWrite)?;
+            // The offset occupies the fourth byte of MSR_TEMPERATURE_TARGET.
+            plan.write_register(&path, MSR_TEMPERATURE_TARGET, 24, 8, tcc_offset.into())
+                .map_err(ModelError::Tcc)?;
         }
 
         Ok(())
     }
 }
 
-pub struct ModelProfiles {
-    pub balanced:    ModelProfile,
-    pub performance: ModelProfile,
-    pub battery:     ModelProfile,
+/// The TCC offset in bits 24 to 29 of MSR_TEMPERATURE_TARGET, without the lock in bit 31.
+fn tcc_offset(register: u64) -> u8 { ((register >> 24) & 0x3F) as u8 }
+
+/// The powercap zones of every package, on every socket.
+fn package_zones() -> Result<Vec<Zone>, PowercapError> {
+    Ok(powercap::zones()?.into_iter().filter(Zone::is_package).collect())
 }
 
-impl ModelProfiles {
-    pub fn new() -> Option<Self> {
-        let model_line =
-            fs::read_to_string("/sys/class/dmi/id/product_version").unwrap_or_default();
-        match model_line.trim() {
-            "galp5" => Some(ModelProfiles {
-                balanced:    ModelProfile {
-                    pl1:        Some(28),
-                    pl2:        None,     // galp5 doesn't like setting pl2
-                    tcc_offset: Some(12), // 88 C
-                },
-                performance: ModelProfile {
-                    pl1:        Some(40),
-                    pl2:        None,    // galp5 doesn't like setting pl2
-                    tcc_offset: Some(7), // 93 C
-                },
-                battery:     ModelProfile {
-                    pl1:        Some(12),
-                    pl2:        None,     // galp5 doesn't like setting pl2
-                    tcc_offset: Some(32), // 68 C
-                },
-            }),
-            "lemp9" => Some(ModelProfiles {
-                balanced:    ModelProfile {
-                    pl1:        Some(20),
-                    pl2:        Some(40), // Upped from 30
-                    tcc_offset: Some(12), // 88 C
-                },
-                performance: ModelProfile {
-                    pl1:        Some(30),
-                    pl2:        Some(50),
-                    tcc_offset: Some(2), // 98 C
-                },
-                battery:     ModelProfile {
-                    pl1:        Some(10),
-                    pl2:        Some(30),
-                    tcc_offset: Some(32), // 68 C
-                },
-            }),
-            _ => None,
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{plan::Operation, sysroot::FakeRoot};
+
+    #[test]
+    fn battery_profile_applies_to_tree() {
+        let root = FakeRoot::new("profiles");
+        root.write("/proc/sys/vm/laptop_mode", "0\n");
+        root.write("/proc/sys/vm/dirty_expire_centisecs", "3000\n");
+        root.write("/proc/sys/vm/dirty_writeback_centisecs", "500\n");
+        root.write("/sys/devices/system/cpu/possible", "0-1\n");
+        for cpu in 0..2 {
+            let dir = format!("/sys/devices/system/cpu/cpu{}/cpufreq/", cpu);
+            root.write(&[&dir, "scaling_driver"].concat(), "intel_pstate\n");
+            root.write(&[&dir, "cpuinfo_min_freq"].concat(), "400000\n");
+            root.write(&[&dir, "cpuinfo_max_freq"].concat(), "4000000\n");
+            root.write(
+                &[&dir, "energy_performance_available_preferences"].concat(),
+                "default performance balance_performance balance_power power \n",
+            );
+            root.write(&[&dir, "energy_performance_preference"].concat(), "balance_performance\n");
+            root.write(
+                &format!("/sys/devices/system/cpu/cpu{}/power/energy_perf_bias", cpu),
+                "6\n",
+            );
+        }
+        root.write("/sys/devices/system/cpu/intel_pstate/min_perf_pct", "20\n");
+        root.write("/sys/devices/system/cpu/intel_pstate/max_perf_pct", "100\n");
+        root.write("/sys/devices/system/cpu/intel_pstate/no_turbo", "0\n");
+
+        let mut errors = Vec::new();
+        apply(
+            &mut Plan::new(),
+            &ProfileConfig::battery(),
+            &ModelProfile::default(),
+            &mut Thermald::default(),
+            &mut Offline::default(),
+            &mut errors,
+            false,
+        );
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        assert_eq!(root.read("/proc/sys/vm/laptop_mode"), "2");
+        assert_eq!(root.read("/proc/sys/vm/dirty_expire_centisecs"), "1500");
+        assert_eq!(root.read("/proc/sys/vm/dirty_writeback_centisecs"), "1500");
+        for cpu in 0..2 {
+            let dir = format!("/sys/devices/system/cpu/cpu{}/cpufreq/", cpu);
+            assert_eq!(root.read(&[&dir, "scaling_governor"].concat()), "powersave");
+            assert_eq!(root.read(&[&dir, "scaling_min_freq"].concat()), "400000");
+            assert_eq!(root.read(&[&dir, "scaling_max_freq"].concat()), "2000000");
+            assert_eq!(root.read(&[&dir, "energy_performance_preference"].concat()), "power");
+            assert_eq!(
+                root.read(&format!("/sys/devices/system/cpu/cpu{}/power/energy_perf_bias", cpu)),
+                "15"
+            );
+        }
+        assert_eq!(root.read("/sys/devices/system/cpu/intel_pstate/min_perf_pct"), "0");
+        assert_eq!(root.read("/sys/devices/system/cpu/intel_pstate/max_perf_pct"), "50");
+        assert_eq!(root.read("/sys/devices/system/cpu/intel_pstate/no_turbo"), "1");
+    }
+
+    #[test]
+    fn model_limits_are_read_back() {
+        let root = FakeRoot::new("limits");
+        for (id, pl1) in &[("intel-rapl:0", "28000000\n"), ("intel-rapl-mmio:0", "25000000\n")] {
+            let dir = format!("{}/{}/", powercap::POWERCAP_PATH, id);
+            root.write(&[&dir, "name"].concat(), "package-0\n");
+            root.write(&[&dir, "constraint_0_name"].concat(), "long_term\n");
+            root.write(&[&dir, "constraint_0_power_limit_uw"].concat(), pl1);
+            root.write(&[&dir, "constraint_1_name"].concat(), "short_term\n");
+            root.write(&[&dir, "constraint_1_power_limit_uw"].concat(), "40600000\n");
         }
+
+        assert_eq!(
+            ModelProfile::get().unwrap(),
+            ModelProfile {
+                pl1:        Some(25),
+                pl2:        Some(41),
+                pl4:        None,
+                tcc_offset: None,
+            }
+        );
+
+        let mut plan = Plan::dry_run();
+        let limits = ModelProfile { pl1: Some(30), ..Default::default() };
+        limits.set(&mut plan).unwrap();
+        assert_eq!(plan.operations().filter(|op| matches!(op, Operation::Write { .. })).count(), 2);
+
+        let limits = ModelProfile { pl4: Some(90), ..Default::default() };
+        assert!(matches!(
+            limits.set(&mut Plan::dry_run()),
+            Err(ModelError::Powercap(PowercapError::NoConstraint { .. }))
+        ));
+
+        // A limit which cannot be read is left out, rather than failing every other limit.
+        let path =
+            format!("{}/intel-rapl-mmio:0/constraint_0_power_limit_uw", powercap::POWERCAP_PATH);
+        root.write(&path, "max\n");
+        assert_eq!(ModelProfile::get().unwrap().pl1, Some(28));
+    }
+
+    #[test]
+    fn tcc_offset_is_masked() {
+        // Bit 31 locks the register, and is not part of the offset.
+        assert_eq!(tcc_offset(0x8A64_0000), 10);
+        assert_eq!(tcc_offset(0x0F64_0000), 15);
     }
 }
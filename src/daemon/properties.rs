@@ -0,0 +1,128 @@
+// Copyright 2022 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Properties of the daemon, which are signalled with `PropertiesChanged` when they change.
+//!
+//! Their values are compared on each step of the daemon loop, so that changes made outside of
+//! the daemon, such as plugging in the AC adapter or writing the charge thresholds directly, are
+//! signalled as well. The graphics and the charge thresholds are slower to read, so they are only
+//! read every few steps, and right after the daemon changes them. The getter methods which preceded
+//! them are kept for existing clients.
+
+use super::{autoswitch, PowerDaemon};
+use crate::{charge_thresholds::get_charge_thresholds, Power, DBUS_IFACE, DBUS_PATH};
+use dbus::{
+    arg::{RefArg, Variant},
+    blocking::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged,
+    message::SignalArgs,
+    Message,
+};
+use dbus_crossroads::IfaceBuilder;
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Properties {
+    pub profile:           String,
+    pub graphics:          String,
+    pub graphics_power:    bool,
+    pub charge_thresholds: (u8, u8),
+    pub ac_online:         bool,
+}
+
+impl Properties {
+    /// Reads the properties. Unless `all` is set, the slow ones are kept as they were last read.
+    pub fn read(daemon: &mut PowerDaemon, all: bool) -> Self {
+        let profile = daemon.power_profile.clone();
+        let ac_online = ac_online();
+        if let (false, Some(last)) = (all, &daemon.properties) {
+            return Self { profile, ac_online, ..last.clone() };
+        }
+
+        Self {
+            profile,
+            graphics: graphics(daemon),
+            graphics_power: graphics_power(daemon),
+            charge_thresholds: charge_thresholds(),
+            ac_online,
+        }
+    }
+
+    /// The `PropertiesChanged` signal for the properties which differ from `previous`, if any.
+    pub fn changed_message(&self, previous: &Self) -> Option<Message> {
+        let mut changed: HashMap<String, Variant<Box<dyn RefArg>>> = HashMap::new();
+        let mut insert = |name: &str, value: Box<dyn RefArg>| {
+            changed.insert(name.to_owned(), Variant(value));
+        };
+
+        if self.profile != previous.profile {
+            insert("Profile", Box::new(self.profile.clone()));
+        }
+        if self.graphics != previous.graphics {
+            insert("Graphics", Box::new(self.graphics.clone()));
+        }
+        if self.graphics_power != previous.graphics_power {
+            insert("GraphicsPower", Box::new(self.graphics_power));
+        }
+        if self.charge_thresholds != previous.charge_thresholds {
+            insert("ChargeThresholds", Box::new(self.charge_thresholds));
+        }
+        if self.ac_online != previous.ac_online {
+            insert("AcOnline", Box::new(self.ac_online));
+        }
+
+        if changed.is_empty() {
+            return None;
+        }
+
+        let signal = PropertiesPropertiesChanged {
+            interface_name:         DBUS_IFACE.to_owned(),
+            changed_properties:     changed,
+            invalidated_properties: Vec::new(),
+        };
+        Some(signal.to_emit_message(&DBUS_PATH.into()))
+    }
+}
+
+/// The graphics mode, or an empty string if it cannot be determined.
+fn graphics(daemon: &mut PowerDaemon) -> String { daemon.get_graphics().unwrap_or_default() }
+
+/// Whether the discrete GPU is powered, which it never is on systems without switchable graphics.
+fn graphics_power(daemon: &mut PowerDaemon) -> bool { daemon.get_graphics_power().unwrap_or(false) }
+
+/// The start and end charge thresholds, which are 0 and 100 without support for thresholds.
+fn charge_thresholds() -> (u8, u8) { get_charge_thresholds().unwrap_or((0, 100)) }
+
+/// Whether the AC adapter is plugged in, which it is taken to be on systems without one.
+fn ac_online() -> bool { autoswitch::ac_online().unwrap_or(true) }
+
+pub fn register(b: &mut IfaceBuilder<PowerDaemon>) {
+    b.property("Profile").get(|_, daemon| Ok(daemon.power_profile.clone()));
+    b.property("Graphics").get(|_, daemon| Ok(graphics(daemon)));
+    b.property("GraphicsPower").get(|_, daemon| Ok(graphics_power(daemon)));
+    b.property("ChargeThresholds").get(|_, _| Ok(charge_thresholds()));
+    b.property("AcOnline").get(|_, _| Ok(ac_online()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_changed_properties_are_signalled() {
+        let previous = Properties {
+            profile:           "Balanced".into(),
+            graphics:          "hybrid".into(),
+            graphics_power:    true,
+            charge_thresholds: (0, 100),
+            ac_online:         true,
+        };
+        assert!(previous.changed_message(&previous).is_none());
+
+        let current = Properties { ac_online: false, ..previous.clone() };
+        let message = current.changed_message(&previous).unwrap();
+        let signal = PropertiesPropertiesChanged::from_message(&message).unwrap();
+        assert_eq!(signal.interface_name, DBUS_IFACE);
+        assert_eq!(signal.changed_properties.keys().collect::<Vec<_>>(), vec!["AcOnline"]);
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright 2022 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Original values of the tunables changed by the daemon, so that they can be put back.
+
+use crate::{
+    plan::{Operation, Plan, Step},
+    util,
+};
+use std::path::PathBuf;
+
+#[derive(Debug, PartialEq)]
+enum Original {
+    File { path: PathBuf, value: String },
+    Register { path: PathBuf, register: u64, shift: u32, width: u32, value: u64 },
+    WifiPowerSave { interface: String, value: bool },
+    Unit { unit: String, value: bool },
+}
+
+impl Original {
+    fn from_operation(operation: &Operation) -> Option<Self> {
+        match operation {
+            // Brightness is left to the user once it has been lowered.
+            Operation::Write { path, .. } if path.ends_with("brightness") => None,
+            Operation::Write { path, old, .. } => {
+                // Attributes which list the alternatives, such as the ASPM policy, are restored
+                // by writing the one which was selected.
+                let old = old.as_deref()?;
+                let value = util::selected(old).unwrap_or(old).to_owned();
+                Some(Original::File { path: path.clone(), value })
+            }
+            Operation::Register { path, register, shift, width, old, .. } => {
+                Some(Original::Register {
+                    path:     path.clone(),
+                    register: *register,
+                    shift:    *shift,
+                    width:    *width,
+                    value:    (*old)?,
+                })
+            }
+            Operation::WifiPowerSave { interface, old, .. } => {
+                Some(Original::WifiPowerSave { interface: interface.clone(), value: (*old)? })
+            }
+            Operation::Unit { unit, old, .. } => {
+                Some(Original::Unit { unit: unit.clone(), value: (*old)? })
+            }
+            // A command is not a tunable, and whatever it changes is recorded by its own
+            // operation, such as the register written after `modprobe msr`.
+            Operation::Command { .. } => None,
+        }
+    }
+
+    fn is_same_tunable(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Original::File { path: a, .. }, Original::File { path: b, .. }) => a == b,
+            (
+                Original::Register { path: a, register: ra, shift: sa, .. },
+                Original::Register { path: b, register: rb, shift: sb, .. },
+            ) => a == b && ra == rb && sa == sb,
+            (
+                Original::WifiPowerSave { interface: a, .. },
+                Original::WifiPowerSave { interface: b, .. },
+            ) => a == b,
+            (Original::Unit { unit: a, .. }, Original::Unit { unit: b, .. }) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// The values that tunables had before the daemon first changed them.
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    originals: Vec<Original>,
+}
+
+impl Snapshot {
+    pub fn is_empty(&self) -> bool { self.originals.is_empty() }
+
+    /// Remembers the value of each tunable changed by the steps which succeeded, unless it was
+    /// changed before and its original value is already known.
+    pub fn record(&mut self, steps: &[Step]) {
+        let operations = steps.iter().filter(|step| step.failure.is_none());
+        for original in operations.filter_map(|step| Original::from_operation(&step.operation)) {
+            if !self.originals.iter().any(|known| known.is_same_tunable(&original)) {
+                self.originals.push(original);
+            }
+        }
+    }
+
+    /// Puts back the original values, in the reverse of the order in which they were first
+    /// changed, and forgets them. Returns the failures, which do not stop the other values from
+    /// being restored.
+    pub fn restore(&mut self, plan: &mut Plan) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        for original in self.originals.drain(..).rev() {
+            let result = match original {
+                Original::File { ref path, ref value } => {
+                    plan.write(path, value).map_err(|why| (path.display().to_string(), why))
+                }
+                Original::Register { ref path, register, shift, width, value } => plan
+                    .write_register(path, register, shift, width, value)
+                    .map_err(|why| (path.display().to_string(), why)),
+                Original::WifiPowerSave { ref interface, value } => plan
+                    .set_wifi_power_save(interface, value)
+                    .map_err(|why| (format!("power save of {}", interface), why)),
+                Original::Unit { ref unit, value } => {
+                    plan.set_unit_active(unit, value, None);
+                    Ok(())
+                }
+            };
+
+            if let Err((target, why)) = result {
+                errors.push(format!("failed to restore {}: {}", target, why));
+            }
+        }
+
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::Failure;
+    use std::io;
+
+    fn write(path: &str, old: &str, new: &str) -> Step {
+        let operation =
+            Operation::Write { path: path.into(), old: Some(old.into()), new: new.into() };
+        Step { setting: "test", operation, failure: None, pending: false }
+    }
+
+    #[test]
+    fn keeps_first_value() {
+        let mut snapshot = Snapshot::default();
+        snapshot.record(&[write("/a", "1", "2"), write("/b", "x", "y")]);
+        snapshot.record(&[write("/a", "2", "3")]);
+        snapshot.record(&[write("/c", "default [powersave] performance", "performance")]);
+
+        assert_eq!(
+            snapshot.originals,
+            vec![
+                Original::File { path: "/a".into(), value: "1".into() },
+                Original::File { path: "/b".into(), value: "x".into() },
+                Original::File { path: "/c".into(), value: "powersave".into() },
+            ]
+        );
+
+        let mut plan = Plan::dry_run();
+        assert!(snapshot.restore(&mut plan).is_empty());
+        assert!(snapshot.is_empty());
+        assert_eq!(
+            plan.operations().cloned().collect::<Vec<_>>(),
+            vec![
+                Operation::Write { path: "/c".into(), old: None, new: "powersave".into() },
+                Operation::Write { path: "/b".into(), old: None, new: "x".into() },
+                Operation::Write { path: "/a".into(), old: None, new: "1".into() },
+            ]
+        );
+
+        // Neither commands nor steps which failed leave a value to restore.
+        let hdparm = Operation::Command {
+            command: "hdparm".into(),
+            args:    vec!["-B".into(), "128".into(), "/dev/sda".into()],
+        };
+        let mut failed = write("/d", "1", "2");
+        failed.failure = Some(Failure { kind: io::ErrorKind::NotFound, message: "gone".into() });
+        let mut skipped = Snapshot::default();
+        let hdparm =
+            Step { setting: "disks", operation: hdparm, failure: None, pending: false };
+        skipped.record(&[hdparm, failed]);
+        assert!(skipped.is_empty());
+    }
+}
@@ -0,0 +1,141 @@
+// Copyright 2022 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! State which is kept across daemon restarts and reboots.
+
+use crate::{hardware::ModelProfile, sysroot};
+use serde::{Deserialize, Serialize};
+use std::{fmt, fs, io, path::PathBuf};
+
+pub const STATE_DIR: &str = "/var/lib/system76-power";
+
+const STATE_FILE: &str = "state.json";
+
+/// Why a profile was applied.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Reason {
+    /// Applied when the daemon started.
+    Startup,
+    /// Requested by a client.
+    User,
+    /// The AC adapter was plugged in.
+    Ac,
+    /// The AC adapter was unplugged.
+    Battery,
+    /// An application which selects a profile was started, or the last one exited.
+    App,
+    /// An application held a profile, or the last hold was released.
+    Hold,
+}
+
+impl Reason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Reason::Startup => "startup",
+            Reason::User => "user",
+            Reason::Ac => "ac",
+            Reason::Battery => "battery",
+            Reason::App => "app",
+            Reason::Hold => "hold",
+        }
+    }
+
+    /// Whether the profile was chosen, rather than applied for the time being, so that it is
+    /// the one restored at startup.
+    pub fn is_choice(self) -> bool { matches!(self, Reason::User | Reason::Ac | Reason::Battery) }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str(self.as_str()) }
+}
+
+/// The profile applied when the daemon starts.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Startup {
+    /// Restore the last profile that was applied.
+    #[default]
+    Restore,
+    /// Always start with the given profile.
+    Profile(String),
+}
+
+impl Startup {
+    /// Parses `restore`, or else the name of a profile.
+    pub fn parse(value: &str) -> Self {
+        if value == "restore" {
+            Startup::Restore
+        } else {
+            Startup::Profile(value.to_owned())
+        }
+    }
+}
+
+impl fmt::Display for Startup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Startup::Restore => f.write_str("restore"),
+            Startup::Profile(profile) => f.write_str(profile),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+pub struct State {
+    /// The last profile that was applied, other than at startup.
+    pub profile:    Option<String>,
+    /// Why the last profile was applied.
+    pub reason:     Option<Reason>,
+    pub startup:    Startup,
+    /// Overrides whether automatic switching on AC changes is enabled in the daemon config.
+    pub autoswitch: Option<bool>,
+    /// Power limits set by the user, which take the place of those of the model.
+    pub limits:     ModelProfile,
+}
+
+impl State {
+    fn path() -> PathBuf { sysroot::path(STATE_DIR).join(STATE_FILE) }
+
+    /// Loads the state, or the default state if there is none or it cannot be read.
+    pub fn load() -> Self {
+        let path = Self::path();
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(why) => {
+                if why.kind() != io::ErrorKind::NotFound {
+                    log::warn!("failed to read {:?}: {}", path, why);
+                }
+
+                return Self::default();
+            }
+        };
+
+        serde_json::from_str(&contents).unwrap_or_else(|why| {
+            log::warn!("ignoring invalid state in {:?}: {}", path, why);
+            Self::default()
+        })
+    }
+
+    /// The profile to apply when the daemon starts.
+    pub fn startup_profile(&self) -> Option<&str> {
+        match self.startup {
+            Startup::Restore => self.profile.as_deref(),
+            Startup::Profile(ref profile) => Some(profile),
+        }
+    }
+
+    /// Stores the state, replacing the previous file atomically.
+    pub fn save(&self) -> io::Result<()> {
+        let path = Self::path();
+        let temporary = path.with_extension("json.new");
+        let contents = serde_json::to_string_pretty(self)
+            .map_err(|why| io::Error::new(io::ErrorKind::InvalidData, why))?;
+
+        fs::create_dir_all(sysroot::path(STATE_DIR))?;
+        fs::write(&temporary, contents)?;
+        fs::rename(&temporary, &path)
+    }
+}
@@ -0,0 +1,72 @@
+// Copyright 2022 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Coordination with thermald, which sets the RAPL power limits on its own and would replace
+//! those of a profile.
+//!
+//! thermald is stopped only while a profile manages the power limits, and started again once a
+//! profile which leaves them alone is applied. The snapshot of the daemon starts it again when
+//! the original values are restored, such as on shutdown.
+//!
+//! Plans only record when the unit should be started or stopped. The daemon performs those
+//! changes through its own D-Bus connection, and refreshes the state of the unit the same way.
+
+use crate::plan::Plan;
+
+pub const UNIT: &str = "thermald.service";
+
+/// The last known state of thermald, kept by the daemon.
+#[derive(Debug, Default)]
+pub struct Thermald {
+    /// Whether thermald is active, or `None` if it is not installed.
+    pub active: Option<bool>,
+    /// Whether thermald was stopped by the daemon.
+    stopped:    bool,
+}
+
+impl Thermald {
+    /// Stops thermald if it is running, so that it does not replace the power limits.
+    pub fn stop(&mut self, plan: &mut Plan) {
+        if self.active != Some(true) {
+            return;
+        }
+
+        log::info!("stopping thermald while the profile manages the power limits");
+        plan.set_unit_active(UNIT, false, self.active);
+        if !plan.is_dry_run() {
+            self.active = Some(false);
+            self.stopped = true;
+        }
+    }
+
+    /// Starts thermald again if the daemon stopped it, and nothing else has started it since.
+    pub fn restore(&mut self, plan: &mut Plan) {
+        if !self.stopped {
+            return;
+        }
+
+        if self.active == Some(false) {
+            log::info!("starting thermald, as the profile leaves the power limits to it");
+            plan.set_unit_active(UNIT, true, self.active);
+        }
+
+        if !plan.is_dry_run() {
+            self.active = Some(true);
+            self.stopped = false;
+        }
+    }
+
+    /// Forgets that thermald was stopped, once the snapshot has started it again.
+    pub fn restored(&mut self) { self.stopped = false; }
+
+    /// Describes whether thermald is installed, running, or stopped by the daemon.
+    pub fn state(&self) -> &'static str {
+        match self.active {
+            None => "not installed",
+            Some(true) => "running",
+            Some(false) if self.stopped => "stopped by system76-power",
+            Some(false) => "stopped",
+        }
+    }
+}
@@ -2,18 +2,23 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
-use crate::errors::DiskPowerError;
-use std::{
-    fs::{read_to_string, write},
-    path::{Path, PathBuf},
-    process::{Command, Stdio},
-};
+use crate::{errors::DiskPowerError, plan::Plan, sysroot};
+use std::{env, fs::read_to_string, path::PathBuf, sync::OnceLock};
 
 const AUTOSUSPEND: &str = "device/power/autosuspend_delay_ms";
 
+/// Whether hdparm is installed, which is looked up in `PATH` the first time it is needed.
+fn has_hdparm() -> bool {
+    static HAS_HDPARM: OnceLock<bool> = OnceLock::new();
+    *HAS_HDPARM.get_or_init(|| {
+        env::var_os("PATH")
+            .map_or(false, |paths| env::split_paths(&paths).any(|dir| dir.join("hdparm").is_file()))
+    })
+}
+
 pub trait DiskPower {
-    fn set_apm_level(&self, level: u8) -> Result<(), DiskPowerError>;
-    fn set_autosuspend_delay(&self, ms: i32) -> Result<(), DiskPowerError>;
+    fn set_apm_level(&self, plan: &mut Plan, level: u8) -> Result<(), DiskPowerError>;
+    fn set_autosuspend_delay(&self, plan: &mut Plan, ms: i32) -> Result<(), DiskPowerError>;
 }
 
 pub struct Disks(Vec<Disk>);
@@ -21,7 +26,7 @@ pub struct Disks(Vec<Disk>);
 impl Default for Disks {
     fn default() -> Disks {
         let mut disks = Vec::new();
-        let blocks = match Path::new("/sys/block").read_dir() {
+        let blocks = match sysroot::path("/sys/block").read_dir() {
             Ok(blocks) => blocks,
             Err(why) => {
                 log::warn!("unable to get block devices: {}", why);
@@ -38,8 +43,8 @@ impl Default for Disks {
                     }
 
                     disks.push(Disk {
-                        path:          PathBuf::from(["/dev/", &name].concat()),
-                        block:         PathBuf::from(["/sys/block/", &name].concat()),
+                        path:          sysroot::path(["/dev/", &name].concat()),
+                        block:         device.path(),
                         is_rotational: {
                             read_to_string(device.path().join("queue/rotational"))
                                 .ok()
@@ -54,16 +59,20 @@ impl Default for Disks {
     }
 }
 
+impl Disks {
+    /// The rotational disks, which are the ones that power management applies to.
+    pub fn rotational(&self) -> impl Iterator<Item = &Disk> {
+        self.0.iter().filter(|dev| dev.is_rotational)
+    }
+}
+
 impl DiskPower for Disks {
-    fn set_apm_level(&self, level: u8) -> Result<(), DiskPowerError> {
-        self.0.iter().filter(|dev| dev.is_rotational).try_for_each(|dev| dev.set_apm_level(level))
+    fn set_apm_level(&self, plan: &mut Plan, level: u8) -> Result<(), DiskPowerError> {
+        self.rotational().try_for_each(|dev| dev.set_apm_level(plan, level))
     }
 
-    fn set_autosuspend_delay(&self, ms: i32) -> Result<(), DiskPowerError> {
-        self.0
-            .iter()
-            .filter(|dev| dev.is_rotational)
-            .try_for_each(|dev| dev.set_autosuspend_delay(ms))
+    fn set_autosuspend_delay(&self, plan: &mut Plan, ms: i32) -> Result<(), DiskPowerError> {
+        self.rotational().try_for_each(|dev| dev.set_autosuspend_delay(plan, ms))
     }
 }
 
@@ -74,22 +83,36 @@ pub struct Disk {
 }
 
 impl DiskPower for Disk {
-    fn set_apm_level(&self, level: u8) -> Result<(), DiskPowerError> {
+    fn set_apm_level(&self, plan: &mut Plan, level: u8) -> Result<(), DiskPowerError> {
+        // hdparm is only recommended, so the level is left alone without it, and the plan
+        // does not report a command which was never run.
+        if !has_hdparm() {
+            log::debug!("hdparm is not installed, skipping APM level of {:?}", self.path);
+            return Ok(());
+        }
+
         log::debug!("Setting APM level on {:?} to {}", &self.path, level);
-        Command::new("hdparm")
-            .arg("-B")
-            .arg(level.to_string())
-            .arg(&self.path)
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .status()
-            .map_err(|why| DiskPowerError::ApmLevel(self.path.to_owned(), level, why))
-            .map(|_| ())
+        let status = plan
+            .run("hdparm", &["-B", &level.to_string(), &self.path.to_string_lossy()])
+            .map_err(|why| DiskPowerError::ApmLevel(self.path.to_owned(), level, why))?;
+
+        if !status.success() {
+            return Err(DiskPowerError::ApmLevelExitStatus(self.path.to_owned(), level, status));
+        }
+
+        Ok(())
     }
 
-    fn set_autosuspend_delay(&self, ms: i32) -> Result<(), DiskPowerError> {
+    fn set_autosuspend_delay(&self, plan: &mut Plan, ms: i32) -> Result<(), DiskPowerError> {
+        let path = self.block.join(AUTOSUSPEND);
+
+        // Not every disk supports runtime power management.
+        if !path.exists() {
+            return Ok(());
+        }
+
         log::debug!("Setting autosuspend delay on {:?} to {}", &self.block, ms);
-        write(&self.block.join(AUTOSUSPEND), ms.to_string().as_bytes())
+        plan.write(&path, ms)
             .map_err(|why| DiskPowerError::AutosuspendDelay(self.block.to_owned(), ms, why))
     }
 }
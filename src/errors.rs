@@ -7,18 +7,28 @@ use std::{io, path::PathBuf, process};
 
 #[derive(Debug, thiserror::Error)]
 pub enum ProfileError {
+    #[error("failed to set ASPM profiles: {0}")]
+    Aspm(#[from] AspmError),
     #[error("failed to set backlight profiles: {0}")]
     Backlight(#[from] BacklightError),
+    #[error("failed to set cpu profiles: {0}")]
+    Cpu(#[from] CpuError),
     #[error("failed to set disk power profiles: {0}")]
     DiskPower(#[from] DiskPowerError),
     #[error("failed to set model profiles: {0}")]
     Model(#[from] ModelError),
     #[error("failed to set pci device profiles: {0}")]
     PciDevice(#[from] PciDeviceError),
+    #[error("failed to set powercap profiles: {0}")]
+    Powercap(#[from] PowercapError),
     #[error("failed to set pstate profiles: {0}")]
     PState(#[from] PStateError),
     #[error("failed to set scsi host profiles: {0}")]
     ScsiHost(#[from] ScsiHostError),
+    #[error("failed to set wifi profiles: {0}")]
+    Wifi(#[from] WifiError),
+    #[error("failed to write {:?}: {}", _0, _1)]
+    Write(PathBuf, io::Error),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -27,38 +37,74 @@ pub enum BacklightError {
     Set(String, io::Error),
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum CpuError {
+    #[error("CPU {} cannot be taken offline", _0)]
+    NotHotpluggable(usize),
+    #[error("SMT cannot be controlled, as its state is '{}'", _0)]
+    SmtControl(String),
+    #[error("failed to write {:?}: {}", _0, _1)]
+    Write(PathBuf, io::Error),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AspmError {
+    #[error("failed to set ASPM policy to {}: {}", _0, _1)]
+    Policy(String, io::Error),
+    #[error("failed to set {} of {}: {}", state, device, why)]
+    LinkState { device: String, state: String, why: io::Error },
+    #[error("unknown link state '{}'", _0)]
+    UnknownLinkState(String),
+    #[error("ASPM policy {} is not supported by the kernel", _0)]
+    Unsupported(String),
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum DiskPowerError {
     #[error("failed to set disk APM level on {:?} to {}: {}", _0, _1, _2)]
     ApmLevel(PathBuf, u8, io::Error),
+    #[error("failed to set disk APM level on {:?} to {}: hdparm exited with {}", _0, _1, _2)]
+    ApmLevelExitStatus(PathBuf, u8, process::ExitStatus),
     #[error("failed to set disk autosuspend delay on {:?} to {}: {}", _0, _1, _2)]
     AutosuspendDelay(PathBuf, i32, io::Error),
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum WifiError {
+    #[error("failed to list wireless interfaces: {}", _0)]
+    Interfaces(io::Error),
+    #[error("failed to set power save on {}: {}", _0, _1)]
+    PowerSave(String, io::Error),
+    #[error("failed to reload {} module: {}", _0, _1)]
+    Reload(&'static str, io::Error),
+    #[error("failed to reload {} module: modprobe exited with {}", _0, _1)]
+    ReloadExitStatus(&'static str, process::ExitStatus),
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ModelError {
-    #[error("failed to stop thermald: {}", _0)]
-    Thermald(io::Error),
-    #[error("failed to set PL1: {}", _0)]
-    Pl1(io::Error),
-    #[error("failed to set PL2: {}", _0)]
-    Pl2(io::Error),
+    #[error("failed to set power limits: {}", _0)]
+    Powercap(#[from] PowercapError),
     #[error("failed to modprobe msr: {}", _0)]
     ModprobeIo(io::Error),
     #[error("failed to modprobe msr: {}", _0)]
     ModprobeExitStatus(process::ExitStatus),
-    #[error("failed to open msr: {}", _0)]
-    MsrOpen(io::Error),
-    #[error("failed to seek msr: {}", _0)]
-    MsrSeek(io::Error),
-    #[error("failed to read msr: {}", _0)]
-    MsrRead(io::Error),
-    #[error("failed to write msr: {}", _0)]
-    MsrWrite(io::Error),
     #[error("failed to set TCC: {}", _0)]
     Tcc(io::Error),
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum PowercapError {
+    #[error("no powercap zone matches '{}'", _0)]
+    NoZone(String),
+    #[error("powercap zone {} has no {} constraint", zone, constraint)]
+    NoConstraint { zone: String, constraint: String },
+    #[error("failed to read {:?}: {}", _0, _1)]
+    Read(PathBuf, io::Error),
+    #[error("failed to write {:?}: {}", _0, _1)]
+    Write(PathBuf, io::Error),
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum PciDeviceError {
     #[error("failed to set PCI device runtime PM on {}: {}", _0, _1)]
@@ -68,5 +114,5 @@ pub enum PciDeviceError {
 #[derive(Debug, thiserror::Error)]
 pub enum ScsiHostError {
     #[error("failed to set link time power management policy {} on {}: {}", _0, _1, _2)]
-    LinkTimePolicy(&'static str, String, io::Error),
+    LinkTimePolicy(String, String, io::Error),
 }
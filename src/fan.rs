@@ -4,9 +4,13 @@
 
 #![allow(clippy::inconsistent_digit_grouping)]
 
+use crate::{
+    hardware::{self, FanCurveName},
+    sysroot,
+};
 use std::{
     cell::Cell,
-    cmp, fs, io,
+    cmp, io,
     process::{Command, Stdio},
 };
 use sysfs_class::{HwMon, SysClass};
@@ -32,14 +36,13 @@ pub struct FanDaemon {
 
 impl FanDaemon {
     pub fn new(nvidia_exists: bool) -> Self {
-        let model = fs::read_to_string("/sys/class/dmi/id/product_version").unwrap_or_default();
+        let model = hardware::current_model().unwrap_or_default();
         let mut daemon = FanDaemon {
-            curve: match model.trim() {
-                "thelio-major-r1" => FanCurve::threadripper2(),
-                "thelio-major-r2" | "thelio-major-r2.1" | "thelio-major-b1" | "thelio-major-b2"
-                | "thelio-major-b3" | "thelio-mega-r1" | "thelio-mega-r1.1" => FanCurve::hedt(),
-                "thelio-massive-b1" => FanCurve::xeon(),
-                _ => FanCurve::standard(),
+            curve: match model.fan_curve {
+                FanCurveName::Standard => FanCurve::standard(),
+                FanCurveName::Hedt => FanCurve::hedt(),
+                FanCurveName::Threadripper2 => FanCurve::threadripper2(),
+                FanCurveName::Xeon => FanCurve::xeon(),
             },
             amdgpus: Vec::new(),
             platforms: Vec::new(),
@@ -61,7 +64,7 @@ impl FanDaemon {
         self.platforms.clear();
         self.cpus.clear();
 
-        for hwmon in HwMon::all().map_err(FanDaemonError::HwmonDevices)? {
+        for hwmon in sysroot::class_all::<HwMon>().map_err(FanDaemonError::HwmonDevices)? {
             if let Ok(name) = hwmon.name() {
                 log::debug!("hwmon: {}", name);
 
@@ -331,6 +334,34 @@ pub fn nvidia_temperatures<F: FnMut(u32)>(func: F) -> io::Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::sysroot::FakeRoot;
+
+    #[test]
+    fn hwmon_devices_are_driven() {
+        let root = FakeRoot::new("fan");
+        let hwmon = |id: usize, file: &str| format!("/sys/class/hwmon/hwmon{}/{}", id, file);
+        root.write(&hwmon(0, "name"), "system76_io\n");
+        root.write(&hwmon(1, "name"), "coretemp\n");
+        root.write(&hwmon(1, "temp1_input"), "65000\n");
+        root.write(&hwmon(2, "name"), "amdgpu\n");
+        root.write(&hwmon(2, "temp1_input"), "72000\n");
+        // Sensors other than those of the CPU and GPU are ignored.
+        root.write(&hwmon(3, "name"), "acpitz\n");
+        root.write(&hwmon(3, "temp1_input"), "95000\n");
+
+        let mut fan = FanDaemon::new(false);
+        assert_eq!(fan.get_temp(), Some(72000));
+
+        fan.step();
+        let duty = fan.get_duty(72000).unwrap().to_string();
+        assert_eq!(root.read(&hwmon(0, "pwm1_enable")), "1");
+        assert_eq!(root.read(&hwmon(0, "pwm1")), duty);
+        assert_eq!(root.read(&hwmon(0, "pwm2")), duty);
+
+        // The fans are returned to automatic control when the daemon stops.
+        drop(fan);
+        assert_eq!(root.read(&hwmon(0, "pwm1_enable")), "2");
+    }
 
     #[test]
     fn duty_interpolation() {
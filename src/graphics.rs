@@ -2,7 +2,7 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
-use crate::{module::Module, pci::PciBus};
+use crate::{hardware, module::Module, pci::PciBus, sysroot};
 use serde::{Deserialize, Serialize};
 use std::{
     fs,
@@ -74,25 +74,6 @@ EndSection
 
 const PRIME_DISCRETE_PATH: &str = "/etc/prime-discrete";
 
-const EXTERNAL_DISPLAY_REQUIRES_NVIDIA: &[&str] = &[
-    "addw1",
-    "addw2",
-    "gaze14",
-    "gaze15",
-    "gaze16-3050",
-    "gaze16-3060",
-    "gaze16-3060-b",
-    "gaze17-3050",
-    "gaze17-3060-b",
-    "kudu6",
-    "oryp4",
-    "oryp4-b",
-    "oryp5",
-    "oryp6",
-    "oryp7",
-    "oryp8",
-];
-
 #[derive(Debug, thiserror::Error)]
 pub enum GraphicsDeviceError {
     #[error("failed to execute {} command: {}", cmd, why)]
@@ -247,7 +228,7 @@ impl Graphics {
         log::info!("Rescanning PCI bus");
         bus.rescan()?;
 
-        let devs = PciDevice::all()?;
+        let devs = sysroot::class_all::<PciDevice>()?;
 
         let functions = |parent: &PciDevice| -> Vec<PciDevice> {
             let mut functions = Vec::new();
@@ -318,14 +299,13 @@ impl Graphics {
     pub fn get_external_displays_require_dgpu(&self) -> Result<bool, GraphicsDeviceError> {
         self.switchable_or_fail()?;
 
-        let model = fs::read_to_string("/sys/class/dmi/id/product_version")
-            .map_err(GraphicsDeviceError::SysFs)?;
+        let model = hardware::product_version().map_err(GraphicsDeviceError::SysFs)?;
 
-        Ok(EXTERNAL_DISPLAY_REQUIRES_NVIDIA.contains(&model.trim()))
+        Ok(hardware::model(&model).unwrap_or_default().external_display_requires_nvidia)
     }
 
     fn get_nvidia_device(&self, id: u16) -> Result<NvidiaDevice, GraphicsDeviceError> {
-        let docs: Vec<path::PathBuf> = fs::read_dir("/usr/share/doc")
+        let docs: Vec<path::PathBuf> = fs::read_dir(sysroot::path("/usr/share/doc"))
             .map_err(|e| {
                 GraphicsDeviceError::Json(io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
             })?
@@ -380,7 +360,7 @@ impl Graphics {
 
         self.switchable_or_fail()?;
 
-        let product = fs::read_to_string("/sys/class/dmi/id/product_version")
+        let product = fs::read_to_string(sysroot::path("/sys/class/dmi/id/product_version"))
             .map_err(GraphicsDeviceError::SysFs)
             .map(|s| s.trim().to_string())?;
         let blacklisted = DEFAULT_INTEGRATED.contains(&product.as_str());
@@ -388,7 +368,7 @@ impl Graphics {
         let runtimepm = self.gpu_supports_runtimepm().unwrap_or_default();
 
         // Only default to hybrid on System76 models
-        let vendor = fs::read_to_string("/sys/class/dmi/id/sys_vendor")
+        let vendor = fs::read_to_string(sysroot::path("/sys/class/dmi/id/sys_vendor"))
             .map_err(GraphicsDeviceError::SysFs)
             .map(|s| s.trim().to_string())?;
 
@@ -402,13 +382,14 @@ impl Graphics {
     }
 
     fn get_prime_discrete() -> Result<String, GraphicsDeviceError> {
-        fs::read_to_string(PRIME_DISCRETE_PATH)
+        fs::read_to_string(sysroot::path(PRIME_DISCRETE_PATH))
             .map_err(GraphicsDeviceError::PrimeModeRead)
             .map(|mode| mode.trim().to_owned())
     }
 
     fn set_prime_discrete(mode: &str) -> Result<(), GraphicsDeviceError> {
-        fs::write(PRIME_DISCRETE_PATH, mode).map_err(GraphicsDeviceError::PrimeModeWrite)
+        fs::write(sysroot::path(PRIME_DISCRETE_PATH), mode)
+            .map_err(GraphicsDeviceError::PrimeModeWrite)
     }
 
     pub fn get_vendor(&self) -> Result<GraphicsMode, GraphicsDeviceError> {
@@ -453,7 +434,7 @@ impl Graphics {
                 .create(true)
                 .truncate(true)
                 .write(true)
-                .open(MODPROBE_PATH)
+                .open(sysroot::path(MODPROBE_PATH))
                 .map_err(GraphicsDeviceError::ModprobeFileOpen)?;
 
             let text = match vendor {
@@ -471,7 +452,7 @@ impl Graphics {
             // uses S0ix or S3 for suspend.
             if vendor != GraphicsMode::Integrated {
                 // XXX: Better way to check?
-                let s0ix = fs::read_to_string("/sys/power/mem_sleep")
+                let s0ix = fs::read_to_string(sysroot::path("/sys/power/mem_sleep"))
                     .unwrap_or_default()
                     .contains("[s2idle]");
 
@@ -493,14 +474,15 @@ impl Graphics {
                 .create(true)
                 .truncate(true)
                 .write(true)
-                .open(XORG_CONF_PATH)
+                .open(sysroot::path(XORG_CONF_PATH))
                 .map_err(GraphicsDeviceError::XserverConf)?;
 
             file.write_all(XORG_CONF_DISCRETE)
                 .and_then(|_| file.sync_all())
                 .map_err(GraphicsDeviceError::XserverConf)?;
-        } else if path::Path::new(XORG_CONF_PATH).exists() {
-            fs::remove_file(XORG_CONF_PATH).map_err(GraphicsDeviceError::XserverConf)?;
+        } else if sysroot::path(XORG_CONF_PATH).exists() {
+            fs::remove_file(sysroot::path(XORG_CONF_PATH))
+                .map_err(GraphicsDeviceError::XserverConf)?;
         }
 
         const SYSTEMCTL_CMD: &str = "systemctl";
@@ -607,7 +589,7 @@ fn sysfs_power_control(pciid: String, mode: GraphicsMode) {
         let pm = if mode == GraphicsMode::Discrete { "on\n" } else { "auto\n" };
         log::info!("Setting power management to {}", pm);
 
-        let control = format!("/sys/bus/pci/devices/{}/power/control", pciid);
+        let control = sysroot::path(format!("/sys/bus/pci/devices/{}/power/control", pciid));
         let file = fs::OpenOptions::new().create(false).truncate(false).write(true).open(control);
 
         #[allow(unused_must_use)]
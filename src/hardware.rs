@@ -0,0 +1,437 @@
+// Copyright 2022 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Quirks of each hardware model, keyed by its DMI product version.
+//!
+//! The database shipped in `data/hardware.json` is built into the binary, and may be replaced
+//! per site by a file at [`HARDWARE_FILE`]. The daemon loads it once at startup with [`load`]. A
+//! replacement which cannot be read, or which has another [`VERSION`], is ignored in favour of
+//! the built-in database.
+
+use crate::{sysroot, Profile};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+
+pub const HARDWARE_FILE: &str = "/etc/system76-power/hardware.json";
+
+/// The version of the database format which is understood.
+pub const VERSION: u32 = 1;
+
+const BUILTIN: &str = include_str!("../data/hardware.json");
+
+static DATABASE: RwLock<Option<Database>> = RwLock::new(None);
+
+#[derive(Debug, thiserror::Error)]
+pub enum HardwareError {
+    #[error("failed to read {:?}: {}", _0, _1)]
+    Read(PathBuf, io::Error),
+    #[error("invalid hardware database in {:?}: {}", _0, _1)]
+    Parse(PathBuf, serde_json::Error),
+    #[error(
+        "hardware database in {:?} has version {}, but only version {} is supported",
+        _0,
+        _1,
+        VERSION
+    )]
+    Version(PathBuf, u32),
+    #[error("invalid entry for '{}' in {:?}: {}", _1, _0, _2)]
+    Invalid(PathBuf, String, &'static str),
+}
+
+/// A number written as a hexadecimal string, such as `"0x6a"`, as in datasheets.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(try_from = "String", bound = "T: TryFrom<u64>")]
+pub struct Hex<T>(pub T);
+
+impl<T: TryFrom<u64>> TryFrom<String> for Hex<T> {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, String> {
+        value
+            .strip_prefix("0x")
+            .and_then(|digits| u64::from_str_radix(digits, 16).ok())
+            .and_then(|number| T::try_from(number).ok())
+            .map(Hex)
+            .ok_or_else(|| format!("'{}' is not a hexadecimal number of the expected size", value))
+    }
+}
+
+/// The database of hardware models.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Database {
+    pub version: u32,
+    pub models:  BTreeMap<String, Model>,
+}
+
+/// What is known about a hardware model. Anything omitted has no quirk.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Model {
+    /// The external display ports are wired to the NVIDIA GPU.
+    pub external_display_requires_nvidia: bool,
+    pub fan_curve:                        FanCurveName,
+    /// GPIOs which signal hot plugging of the external display ports.
+    pub hotplug:                          Option<HotPlug>,
+    /// GPIOs of the mux which switches a port between Mini DisplayPort and USB-C.
+    pub mux:                              Option<Mux>,
+    /// Power limits applied by the `model_limits` setting of each profile.
+    pub limits:                           Option<ModelProfiles>,
+    /// The range within which users may customize each power limit.
+    pub bounds:                           Option<LimitBounds>,
+}
+
+/// The fan curve used by the fan daemon.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FanCurveName {
+    #[default]
+    Standard,
+    Hedt,
+    Threadripper2,
+    Xeon,
+}
+
+/// The hot plug detection GPIOs, whose order is the order of the ports reported by the
+/// `HotPlugDetect` signal.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum HotPlug {
+    /// Pins of a GPIO community of the PCH, where a pin of zero is not connected.
+    Intel {
+        port:    Hex<u8>,
+        pins:    [Hex<u8>; 4],
+        /// Which display port each pin belongs to.
+        #[serde(default)]
+        comment: Option<String>,
+    },
+    /// GPIOs of the AMD FCH.
+    Amd {
+        gpios:   Vec<Hex<u32>>,
+        /// Which display port each GPIO belongs to.
+        #[serde(default)]
+        comment: Option<String>,
+    },
+    /// GPIOs which differ between variants of the model.
+    Variants { by: VariantSource, variants: BTreeMap<String, HotPlug> },
+}
+
+/// How the variants of a model are told apart.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum VariantSource {
+    /// The subsystem device of the host bridge.
+    SubsystemDevice,
+    /// The device ID of the NVIDIA GPU.
+    NvidiaDevice,
+}
+
+/// The `(port, pin)` GPIOs of a DisplayPort mux.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Mux {
+    pub hpd:     (Hex<u8>, Hex<u8>),
+    pub mux:     (Hex<u8>, Hex<u8>),
+    /// Which GPP pads the GPIOs are.
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+/// A `(limit, configured, effective, minimum, maximum)` entry, where values which are unknown
+/// or do not apply are empty.
+pub type LimitEntry = (String, String, String, String, String);
+
+/// A limit of a [`ModelProfile`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Limit {
+    Pl1,
+    Pl2,
+    Pl4,
+    TccOffset,
+}
+
+impl Limit {
+    pub const ALL: [Limit; 4] = [Limit::Pl1, Limit::Pl2, Limit::Pl4, Limit::TccOffset];
+
+    /// The name of the limit in the hardware database and on D-Bus.
+    pub fn name(self) -> &'static str {
+        match self {
+            Limit::Pl1 => "pl1",
+            Limit::Pl2 => "pl2",
+            Limit::Pl4 => "pl4",
+            Limit::TccOffset => "tcc_offset",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|limit| limit.name() == name)
+    }
+}
+
+/// Power limits of every package in watts, and the offset below TjMax in degrees Celsius at
+/// which the thermal control circuit throttles the CPU. Limits which are omitted are left as they
+/// are.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelProfile {
+    pub pl1:        Option<u8>,
+    pub pl2:        Option<u8>,
+    pub pl4:        Option<u8>,
+    pub tcc_offset: Option<u8>,
+}
+
+impl ModelProfile {
+    pub fn limit(&self, limit: Limit) -> Option<u8> {
+        match limit {
+            Limit::Pl1 => self.pl1,
+            Limit::Pl2 => self.pl2,
+            Limit::Pl4 => self.pl4,
+            Limit::TccOffset => self.tcc_offset,
+        }
+    }
+
+    pub fn limit_mut(&mut self, limit: Limit) -> &mut Option<u8> {
+        match limit {
+            Limit::Pl1 => &mut self.pl1,
+            Limit::Pl2 => &mut self.pl2,
+            Limit::Pl4 => &mut self.pl4,
+            Limit::TccOffset => &mut self.tcc_offset,
+        }
+    }
+
+    /// These limits, with those which are set in `overrides` taking their place.
+    pub fn overridden_by(&self, overrides: &Self) -> Self {
+        Self {
+            pl1:        overrides.pl1.or(self.pl1),
+            pl2:        overrides.pl2.or(self.pl2),
+            pl4:        overrides.pl4.or(self.pl4),
+            tcc_offset: overrides.tcc_offset.or(self.tcc_offset),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelProfiles {
+    pub battery:     ModelProfile,
+    pub balanced:    ModelProfile,
+    pub performance: ModelProfile,
+}
+
+impl ModelProfiles {
+    pub fn get(&self, profile: Profile) -> &ModelProfile {
+        match profile {
+            Profile::Battery => &self.battery,
+            Profile::Balanced => &self.balanced,
+            Profile::Performance => &self.performance,
+        }
+    }
+}
+
+/// Inclusive `(minimum, maximum)` ranges of the limits of a [`ModelProfile`]. Limits which are
+/// omitted may not be customized.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitBounds {
+    pub pl1:        Option<(u8, u8)>,
+    pub pl2:        Option<(u8, u8)>,
+    pub pl4:        Option<(u8, u8)>,
+    pub tcc_offset: Option<(u8, u8)>,
+}
+
+impl LimitBounds {
+    pub fn get(&self, limit: Limit) -> Option<(u8, u8)> {
+        match limit {
+            Limit::Pl1 => self.pl1,
+            Limit::Pl2 => self.pl2,
+            Limit::Pl4 => self.pl4,
+            Limit::TccOffset => self.tcc_offset,
+        }
+    }
+}
+
+impl Database {
+    /// The database built into the binary.
+    pub fn builtin() -> Self {
+        Self::parse(Path::new("data/hardware.json"), BUILTIN)
+            .expect("built-in hardware database is invalid")
+    }
+
+    /// Parses and validates a database read from `path`.
+    pub fn parse(path: &Path, contents: &str) -> Result<Self, HardwareError> {
+        let database: Self = serde_json::from_str(contents)
+            .map_err(|why| HardwareError::Parse(path.to_owned(), why))?;
+
+        if database.version != VERSION {
+            return Err(HardwareError::Version(path.to_owned(), database.version));
+        }
+
+        for (name, model) in &database.models {
+            if let Some(ref hotplug) = model.hotplug {
+                hotplug
+                    .validate(false)
+                    .map_err(|why| HardwareError::Invalid(path.to_owned(), name.clone(), why))?;
+            }
+
+            if let Some(ref bounds) = model.bounds {
+                if Limit::ALL
+                    .iter()
+                    .filter_map(|&limit| bounds.get(limit))
+                    .any(|(min, max)| min > max)
+                {
+                    let why = "the minimum of a limit may not exceed its maximum";
+                    return Err(HardwareError::Invalid(path.to_owned(), name.clone(), why));
+                }
+            }
+        }
+
+        Ok(database)
+    }
+
+    /// Reads the replacement database, falling back to the built-in one.
+    pub fn load() -> Self {
+        let path = sysroot::path(HARDWARE_FILE);
+        let result = match fs::read_to_string(&path) {
+            Ok(contents) => Self::parse(&path, &contents),
+            Err(why) if why.kind() == io::ErrorKind::NotFound => return Self::builtin(),
+            Err(why) => Err(HardwareError::Read(path, why)),
+        };
+
+        result.unwrap_or_else(|why| {
+            log::error!("{}; using built-in hardware database", why);
+            Self::builtin()
+        })
+    }
+}
+
+impl HotPlug {
+    fn validate(&self, is_variant: bool) -> Result<(), &'static str> {
+        match self {
+            HotPlug::Intel { .. } => Ok(()),
+            HotPlug::Amd { gpios, .. } if gpios.len() > 4 => {
+                Err("at most 4 hot plug GPIOs are supported")
+            }
+            HotPlug::Amd { .. } => Ok(()),
+            HotPlug::Variants { .. } if is_variant => Err("variants may not have variants"),
+            HotPlug::Variants { variants, .. } => {
+                variants.values().try_for_each(|variant| variant.validate(true))
+            }
+        }
+    }
+}
+
+/// Loads the database which is used from now on.
+pub fn load() {
+    let database = Database::load();
+    log::info!("Loaded hardware database with {} models", database.models.len());
+    *DATABASE.write().unwrap() = Some(database);
+}
+
+/// Finds a model in the loaded database, or in the built-in one if none was loaded.
+pub fn model(name: &str) -> Option<Model> {
+    DATABASE.write().unwrap().get_or_insert_with(Database::builtin).models.get(name).cloned()
+}
+
+/// The DMI product version, which names the model.
+pub fn product_version() -> io::Result<String> {
+    fs::read_to_string(sysroot::path("/sys/class/dmi/id/product_version"))
+        .map(|model| model.trim().to_owned())
+}
+
+/// Finds the model of this machine, if it is known.
+pub fn current_model() -> Option<Model> { product_version().ok().and_then(|name| model(&name)) }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysroot::FakeRoot;
+
+    #[test]
+    fn builtin_database_is_valid() {
+        let database = Database::builtin();
+        assert_eq!(database.version, VERSION);
+
+        let gaze15 = &database.models["gaze15"];
+        assert!(gaze15.external_display_requires_nvidia);
+        match gaze15.hotplug {
+            Some(HotPlug::Variants { by: VariantSource::NvidiaDevice, ref variants }) => {
+                assert!(matches!(
+                    variants["0x2191"],
+                    HotPlug::Intel {
+                        port:    Hex(0x6a),
+                        pins:    [Hex(0x2a), Hex(0), Hex(0x2e), Hex(0)],
+                        comment: Some(_),
+                    }
+                ));
+            }
+            ref other => panic!("unexpected gaze15 hot plug GPIOs: {:?}", other),
+        }
+
+        let lemp9 = database.models["lemp9"].limits.as_ref().unwrap();
+        assert_eq!(
+            *lemp9.get(Profile::Performance),
+            ModelProfile {
+                pl1:        Some(30),
+                pl2:        Some(50),
+                pl4:        None,
+                tcc_offset: Some(2),
+            }
+        );
+        assert_eq!(
+            database.models["lemp9"].bounds.as_ref().unwrap().get(Limit::Pl2),
+            Some((30, 50))
+        );
+        assert_eq!(database.models["thelio-massive-b1"].fan_curve, FanCurveName::Xeon);
+    }
+
+    #[test]
+    fn replacement_is_validated() {
+        let path = Path::new(HARDWARE_FILE);
+        let parse = |contents: &str| Database::parse(path, contents);
+
+        assert!(parse(r#"{ "version": 1, "models": { "x": { "fan_curve": "hedt" } } }"#).is_ok());
+        assert!(matches!(
+            parse(r#"{ "version": 2, "models": {} }"#),
+            Err(HardwareError::Version(..))
+        ));
+        assert!(matches!(
+            parse(r#"{ "version": 1, "models": { "x": { "fan": "hedt" } } }"#),
+            Err(HardwareError::Parse(..))
+        ));
+        assert!(matches!(
+            parse(
+                r#"{ "version": 1, "models": { "x": { "mux": { "hpd": ["0x6a", "0x12e"], "mux": ["0x6b", "0x0a"] } } } }"#
+            ),
+            Err(HardwareError::Parse(..))
+        ));
+        assert!(matches!(
+            parse(
+                r#"{ "version": 1, "models": { "x": { "hotplug": { "type": "amd", "gpios": ["0x1", "0x2", "0x3", "0x4", "0x5"] } } } }"#
+            ),
+            Err(HardwareError::Invalid(..))
+        ));
+        assert!(matches!(
+            parse(r#"{ "version": 1, "models": { "x": { "bounds": { "pl1": [40, 12] } } } }"#),
+            Err(HardwareError::Invalid(..))
+        ));
+
+        let root = FakeRoot::new("hardware");
+        root.write(
+            HARDWARE_FILE,
+            r#"{ "version": 1, "models": { "x": { "fan_curve": "xeon" } } }"#,
+        );
+        let database = Database::load();
+        assert_eq!(database.models.len(), 1);
+        assert_eq!(database.models["x"].fan_curve, FanCurveName::Xeon);
+
+        root.write(HARDWARE_FILE, r#"{ "version": 2, "models": {} }"#);
+        assert_eq!(Database::load(), Database::builtin());
+    }
+}
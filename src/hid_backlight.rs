@@ -2,9 +2,10 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
+use crate::sysroot;
 use hidapi::{HidApi, HidDevice, HidResult};
 use inotify::{Inotify, WatchMask};
-use std::{fs, path::Path};
+use std::fs;
 
 fn keyboard(device: &HidDevice, brightness: u8, color: u32) -> HidResult<()> {
     // TODO: reset
@@ -64,7 +65,7 @@ pub fn daemon() {
         }
     };
 
-    let dir = Path::new("/sys/class/leds/system76_acpi::kbd_backlight");
+    let dir = sysroot::path("/sys/class/leds/system76_acpi::kbd_backlight");
     if !dir.is_dir() {
         log::error!("hid_backlight: no system76_acpi::kbd_backlight led");
         return;
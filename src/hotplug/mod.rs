@@ -5,6 +5,10 @@
 pub mod mux;
 pub mod sideband;
 
+use crate::{
+    hardware::{self, HotPlug, VariantSource},
+    sysroot,
+};
 use sideband::{Sideband, SidebandError, PCR_BASE_ADDRESS};
 use std::{
     fs,
@@ -18,11 +22,11 @@ pub enum HotPlugDetectError {
     #[error("error constructing sideband: {}", _0)]
     Sideband(SidebandError),
     #[error("{} variant '{}' does not support hotplug detection", model, variant)]
-    VariantUnsupported { model: &'static str, variant: String },
+    VariantUnsupported { model: String, variant: String },
     #[error("model '{}' does not support hotplug detection", _0)]
     ModelUnsupported(String),
     #[error("failed to read {}'s subsystem device: {}", model, why)]
-    SubsystemDevice { model: &'static str, why: io::Error },
+    SubsystemDevice { model: String, why: io::Error },
     #[error("failed to open /dev/mem: {}", _0)]
     DevMemAccess(io::Error),
 }
@@ -47,7 +51,7 @@ impl Amd {
         let mem = fs::OpenOptions::new()
             .read(true)
             .write(true)
-            .open("/dev/mem")
+            .open(sysroot::path("/dev/mem"))
             .map_err(HotPlugDetectError::DevMemAccess)?;
 
         Ok(Self { mem, gpios })
@@ -107,181 +111,50 @@ pub struct HotPlugDetect {
 
 impl HotPlugDetect {
     pub unsafe fn new(nvidia_device: Option<String>) -> Result<Self, HotPlugDetectError> {
-        let model = fs::read_to_string("/sys/class/dmi/id/product_version")
-            .map_err(HotPlugDetectError::ProductVersion)?;
-
-        match model.trim() {
-            "addw1" | "addw2" => Ok(Self {
-                integrated: Integrated::Intel(Intel {
-                    sideband: Sideband::new(PCR_BASE_ADDRESS)?,
-                    port:     0x6A,
-                    pins:     [
-                        0x28, // USB-C on rear
-                        0x2a, // HDMI
-                        0x2c, // Mini DisplayPort
-                        0x2e, // USB-C on right
-                    ],
-                }),
-            }),
-            "gaze14" => {
-                let variant =
-                    fs::read_to_string("/sys/bus/pci/devices/0000:00:00.0/subsystem_device")
-                        .map_err(|why| HotPlugDetectError::SubsystemDevice {
-                            model: "gaze14",
-                            why,
-                        })?;
-
-                match variant.trim() {
-                    // NVIDIA GTX 1660 Ti
-                    "0x8550" | "0x8551" => Ok(Self {
-                        integrated: Integrated::Intel(Intel {
-                            sideband: Sideband::new(PCR_BASE_ADDRESS)?,
-                            port:     0x6A,
-                            pins:     [
-                                0x2a, // HDMI
-                                0x00, // Mini DisplayPort (0x2c) is connected to Intel graphics
-                                0x2e, // USB-C
-                                0x00, // Not Connected
-                            ],
-                        }),
-                    }),
-                    // NVIDIA GTX 1650
-                    "0x8560" | "0x8561" => Ok(Self {
-                        integrated: Integrated::Intel(Intel {
-                            sideband: Sideband::new(PCR_BASE_ADDRESS)?,
-                            port:     0x6A,
-                            pins:     [
-                                0x00, // HDMI (0x2a) is connected to Intel graphics
-                                0x2e, // Mini DisplayPort
-                                0x00, // Not Connected
-                                0x00, // Not Connected
-                            ],
-                        }),
-                    }),
-                    other => Err(HotPlugDetectError::VariantUnsupported {
-                        model:   "gaze14",
-                        variant: other.into(),
-                    }),
-                }
+        let model = hardware::product_version().map_err(HotPlugDetectError::ProductVersion)?;
+        let hotplug = hardware::model(&model)
+            .and_then(|quirks| quirks.hotplug)
+            .ok_or_else(|| HotPlugDetectError::ModelUnsupported(model.clone()))?;
+
+        let hotplug = match hotplug {
+            HotPlug::Variants { by, mut variants } => {
+                let variant = match by {
+                    VariantSource::SubsystemDevice => fs::read_to_string(sysroot::path(
+                        "/sys/bus/pci/devices/0000:00:00.0/subsystem_device",
+                    ))
+                    .map_err(|why| HotPlugDetectError::SubsystemDevice {
+                        model: model.clone(),
+                        why,
+                    })?,
+                    VariantSource::NvidiaDevice => {
+                        nvidia_device.unwrap_or_else(|| "unknown".to_string())
+                    }
+                };
+
+                variants.remove(variant.trim()).ok_or_else(|| {
+                    HotPlugDetectError::VariantUnsupported {
+                        model:   model.clone(),
+                        variant: variant.trim().into(),
+                    }
+                })?
             }
-            "gaze15" => {
-                let variant = nvidia_device.unwrap_or_else(|| "unknown".to_string());
+            hotplug => hotplug,
+        };
 
-                match variant.trim() {
-                    // NVIDIA GTX 1660 Ti
-                    "0x2191" => Ok(Self {
-                        integrated: Integrated::Intel(Intel {
-                            sideband: Sideband::new(PCR_BASE_ADDRESS)?,
-                            port:     0x6A,
-                            pins:     [
-                                0x2a, // HDMI
-                                0x00, // Mini DisplayPort (0x2c) is connected to Intel graphics
-                                0x2e, // USB-C
-                                0x00, // Not Connected
-                            ],
-                        }),
-                    }),
-                    // NVIDIA GTX 1650, 1650 Ti
-                    "0x1f99" | "0x1f95" => Ok(Self {
-                        integrated: Integrated::Intel(Intel {
-                            sideband: Sideband::new(PCR_BASE_ADDRESS)?,
-                            port:     0x6A,
-                            pins:     [
-                                0x00, // HDMI (0x2a) is connected to Intel graphics
-                                0x2e, // Mini DisplayPort
-                                0x00, // Not Connected
-                                0x00, // Not Connected
-                            ],
-                        }),
-                    }),
-                    other => Err(HotPlugDetectError::VariantUnsupported {
-                        model:   "gaze15",
-                        variant: other.into(),
-                    }),
-                }
-            }
-            "gaze16-3050" => Ok(Self {
+        match hotplug {
+            HotPlug::Intel { port, pins, .. } => Ok(Self {
                 integrated: Integrated::Intel(Intel {
                     sideband: Sideband::new(PCR_BASE_ADDRESS)?,
-                    port:     0x6A,
-                    pins:     [
-                        0x00, // HDMI (0x52) is connected to Intel graphics
-                        0x58, // Mini DisplayPort
-                        0x00, // Not Connected
-                        0x00, // Not Connected
-                    ],
+                    port:     port.0,
+                    pins:     [pins[0].0, pins[1].0, pins[2].0, pins[3].0],
                 }),
             }),
-            "gaze16-3060" | "gaze16-3060-b" => Ok(Self {
-                integrated: Integrated::Intel(Intel {
-                    sideband: Sideband::new(PCR_BASE_ADDRESS)?,
-                    port:     0x69,
-                    pins:     [
-                        0x02, // Mini DisplayPort
-                        0x04, // USB-C
-                        0x00, // Not Connected
-                        0x00, // Not Connected
-                    ],
-                }),
-            }),
-            "gaze17-3060-b" => Ok(Self {
-                integrated: Integrated::Intel(Intel {
-                    sideband: Sideband::new(PCR_BASE_ADDRESS)?,
-                    port:     0x6E,
-                    pins:     [
-                        0x72, // Mini DisplayPort
-                        0x78, // HDMI
-                        0x00, // Not Connected
-                        0x00, // Not Connected
-                    ],
-                }),
-            }),
-            "kudu6" => {
-                let gpios = vec![
-                    0x02, // USB-C
-                    0x03, // HDMI
-                    0x15, // Mini DisplayPort
-                ];
+            HotPlug::Amd { gpios, .. } => {
+                let gpios = gpios.iter().map(|gpio| gpio.0).collect();
                 Ok(Self { integrated: Integrated::Amd(Amd::new(gpios)?) })
             }
-
-            "oryp4" | "oryp4-b" | "oryp5" => Ok(Self {
-                integrated: Integrated::Intel(Intel {
-                    sideband: Sideband::new(PCR_BASE_ADDRESS)?,
-                    port:     0x6A,
-                    pins:     [
-                        0x28, // USB-C
-                        0x2a, // HDMI
-                        0x2c, // Mini DisplayPort
-                        0x00, // Not Connected
-                    ],
-                }),
-            }),
-            "oryp6" | "oryp7" => Ok(Self {
-                integrated: Integrated::Intel(Intel {
-                    sideband: Sideband::new(PCR_BASE_ADDRESS)?,
-                    port:     0x6A,
-                    pins:     [
-                        0x2a, // HDMI
-                        0x2c, // Mini DisplayPort
-                        0x2e, // USB-C
-                        0x00, // Not Connected
-                    ],
-                }),
-            }),
-            "oryp8" => Ok(Self {
-                integrated: Integrated::Intel(Intel {
-                    sideband: Sideband::new(PCR_BASE_ADDRESS)?,
-                    port:     0x69,
-                    pins:     [
-                        0x02, // Mini DisplayPort
-                        0x04, // HDMI
-                        0x06, // USB-C
-                        0x00, // Not Connected
-                    ],
-                }),
-            }),
-            other => Err(HotPlugDetectError::ModelUnsupported(other.into())),
+            // The database does not allow variants of variants.
+            HotPlug::Variants { .. } => Err(HotPlugDetectError::ModelUnsupported(model)),
         }
     }
 }
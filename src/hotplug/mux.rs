@@ -2,11 +2,13 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
-use crate::hotplug::{
-    sideband::{Sideband, PCR_BASE_ADDRESS},
-    HotPlugDetectError,
+use crate::{
+    hardware,
+    hotplug::{
+        sideband::{Sideband, PCR_BASE_ADDRESS},
+        HotPlugDetectError,
+    },
 };
-use std::fs;
 
 pub struct DisplayPortMux {
     sideband: Sideband,
@@ -16,26 +18,15 @@ pub struct DisplayPortMux {
 
 impl DisplayPortMux {
     pub unsafe fn new() -> Result<Self, HotPlugDetectError> {
-        let model = fs::read_to_string("/sys/class/dmi/id/product_version")
-            .map_err(HotPlugDetectError::ProductVersion)?;
+        let model = hardware::product_version().map_err(HotPlugDetectError::ProductVersion)?;
 
-        match model.trim() {
-            "bonw14" => Ok(Self {
+        match hardware::model(&model).and_then(|quirks| quirks.mux) {
+            Some(mux) => Ok(Self {
                 sideband: Sideband::new(PCR_BASE_ADDRESS)?,
-                hpd:      (0x6A, 0x2E), // GPP_I3
-                mux:      (0x6B, 0x0A), // GPP_K5
+                hpd:      (mux.hpd.0 .0, mux.hpd.1 .0),
+                mux:      (mux.mux.0 .0, mux.mux.1 .0),
             }),
-            "galp2" | "galp3" | "galp3-b" => Ok(Self {
-                sideband: Sideband::new(PCR_BASE_ADDRESS)?,
-                hpd:      (0xAE, 0x31), // GPP_E13
-                mux:      (0xAF, 0x16), // GPP_A22
-            }),
-            "darp5" | "darp6" | "galp3-c" | "galp4" => Ok(Self {
-                sideband: Sideband::new(PCR_BASE_ADDRESS)?,
-                hpd:      (0x6A, 0x4A), // GPP_E13
-                mux:      (0x6E, 0x2C), // GPP_A22
-            }),
-            other => Err(HotPlugDetectError::ModelUnsupported(other.into())),
+            None => Err(HotPlugDetectError::ModelUnsupported(model)),
         }
     }
 
@@ -6,7 +6,8 @@ use libc::{
     c_int, c_void, close, mmap, open, MAP_FAILED, MAP_SHARED, O_RDWR, PROT_READ, PROT_WRITE,
 };
 
-use std::{ffi::CString, io, ptr};
+use crate::sysroot;
+use std::{ffi::CString, io, os::unix::ffi::OsStringExt, ptr};
 
 pub const PCR_BASE_ADDRESS: usize = 0xFD00_0000;
 
@@ -30,7 +31,8 @@ pub struct Sideband {
 
 impl Sideband {
     pub unsafe fn new(sbreg_phys: usize) -> Result<Self, SidebandError> {
-        let mem_str = CString::new("/dev/mem").unwrap();
+        let mem_str = CString::new(sysroot::path("/dev/mem").into_os_string().into_vec())
+            .map_err(|why| SidebandError::DevMemOpen(why.into()))?;
         let memfd: c_int = open(mem_str.as_ptr(), O_RDWR);
         if memfd == -1 {
             return Err(SidebandError::DevMemOpen(io::Error::last_os_error()));
@@ -5,6 +5,7 @@
 #![allow(unused)]
 pub use sysfs_class::RuntimePowerManagement;
 
+use crate::{plan::Plan, sysroot};
 use std::{
     fs::{read_to_string, write},
     path::{Path, PathBuf},
@@ -50,6 +51,18 @@ pub trait KernelParameter {
             log::warn!("{} does not exist", path.display());
         }
     }
+
+    /// Like [`KernelParameter::set`], but performed through a [`Plan`].
+    fn set_with(&self, plan: &mut Plan, value: &str) {
+        let path = self.get_path();
+        if path.exists() {
+            if let Err(why) = plan.write(path, value) {
+                log::error!("{}: failed to set value: {}", path.display(), why)
+            }
+        } else {
+            log::warn!("{} does not exist", path.display());
+        }
+    }
 }
 
 pub trait DeviceList<T> {
@@ -63,16 +76,18 @@ pub trait DeviceList<T> {
 macro_rules! static_parameters {
     ($($struct:tt { $name:tt : $path:expr }),+) => (
         $(
-            pub struct $struct;
+            pub struct $struct {
+                path: PathBuf
+            }
 
-            impl Default for $struct { fn default() -> Self { $struct } }
+            impl Default for $struct {
+                fn default() -> Self { $struct { path: sysroot::path($path) } }
+            }
 
             impl KernelParameter for $struct {
                 const NAME: &'static str = stringify!($name);
 
-                fn get_path(&self) -> &Path {
-                    Path::new($path)
-                }
+                fn get_path(&self) -> &Path { &self.path }
             }
         )+
     );
@@ -88,7 +103,7 @@ macro_rules! dynamic_parameters {
             impl $struct {
                 pub fn new(unique: &str) -> $struct {
                     $struct {
-                        path: PathBuf::from(format!($format, unique))
+                        path: sysroot::path(format!($format, unique))
                     }
                 }
             }
@@ -135,10 +150,9 @@ pub struct Dirty {
 }
 
 impl Dirty {
-    pub fn set_max_lost_work(&self, secs: u32) {
+    pub fn set_max_lost_work(&self, plan: &mut Plan, secs: u32) {
         let centisecs = (u64::from(secs) * 100).to_string();
-        let centisecs = centisecs.as_bytes();
-        self.expire.set(centisecs);
-        self.writeback.set(centisecs);
+        self.expire.set_with(plan, &centisecs);
+        self.writeback.set_with(plan, &centisecs);
     }
 }
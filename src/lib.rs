@@ -7,38 +7,84 @@
 
 pub mod acpi_platform;
 pub mod args;
+pub mod aspm;
 pub mod charge_thresholds;
 pub mod client;
 pub mod cpufreq;
+pub mod cpus;
 pub mod daemon;
 pub mod disks;
 pub mod errors;
 pub mod fan;
 pub mod graphics;
+pub mod hardware;
 pub mod hid_backlight;
 pub mod hotplug;
 pub mod kernel_parameters;
 pub mod logging;
 pub mod modprobe;
 pub mod module;
+pub mod nl80211;
 pub mod pci;
+pub mod plan;
 pub mod polkit;
+pub mod powercap;
 pub mod radeon;
 pub mod snd;
+pub mod sysroot;
+pub mod systemd;
 pub mod util;
 pub mod wifi;
 
+use aspm::LinkState;
 use charge_thresholds::ChargeProfile;
+use hardware::LimitEntry;
+use plan::ReportEntry;
+use powercap::ConstraintEntry;
+use serde::{Deserialize, Serialize};
 
 pub static DBUS_NAME: &str = "com.system76.PowerDaemon";
 pub static DBUS_PATH: &str = "/com/system76/PowerDaemon";
 pub static DBUS_IFACE: &str = "com.system76.PowerDaemon";
 
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Profile {
     Battery,
     Balanced,
     Performance,
 }
+
+impl Profile {
+    pub const BUILTIN: [Profile; 3] = [Profile::Battery, Profile::Balanced, Profile::Performance];
+
+    /// Finds a built-in profile by its identifier, ignoring case.
+    pub fn from_id(id: &str) -> Option<Self> {
+        Self::BUILTIN.iter().copied().find(|profile| profile.id().eq_ignore_ascii_case(id))
+    }
+
+    /// The identifier used for the profile on the command line and in profile definitions.
+    pub fn id(self) -> &'static str {
+        match self {
+            Profile::Battery => "battery",
+            Profile::Balanced => "balanced",
+            Profile::Performance => "performance",
+        }
+    }
+
+    /// The name reported over D-Bus when the profile is active.
+    pub fn title(self) -> &'static str {
+        match self {
+            Profile::Battery => "Battery",
+            Profile::Balanced => "Balanced",
+            Profile::Performance => "Performance",
+        }
+    }
+}
+
+/// A `(cookie, profile, reason, application ID)` entry of a hold on a profile.
+pub type HoldEntry = (u32, String, String, String);
+
 pub trait Power {
     fn performance(&mut self) -> Result<(), String>;
     fn balanced(&mut self) -> Result<(), String>;
@@ -47,6 +93,32 @@ pub trait Power {
     fn get_default_graphics(&mut self) -> Result<String, String>;
     fn get_graphics(&mut self) -> Result<String, String>;
     fn get_profile(&mut self) -> Result<String, String>;
+    fn set_profile(&mut self, name: &str) -> Result<(), String>;
+    fn get_profiles(&mut self) -> Result<Vec<String>, String>;
+    fn hold_profile(
+        &mut self,
+        profile: &str,
+        reason: &str,
+        application_id: &str,
+    ) -> Result<u32, String>;
+    fn release_profile(&mut self, cookie: u32) -> Result<(), String>;
+    fn get_holds(&mut self) -> Result<Vec<HoldEntry>, String>;
+    fn describe_profile(&mut self, name: &str) -> Result<String, String>;
+    fn plan_profile(&mut self, name: &str)
+        -> Result<Vec<(String, String, String, String)>, String>;
+    fn create_profile(&mut self, name: &str, definition: &str) -> Result<(), String>;
+    fn get_startup_profile(&mut self) -> Result<String, String>;
+    fn set_startup_profile(&mut self, startup: &str) -> Result<(), String>;
+    fn get_last_apply_report(&mut self) -> Result<Vec<ReportEntry>, String>;
+    fn restore_defaults(&mut self) -> Result<(), String>;
+    fn get_auto_switch(&mut self) -> Result<bool, String>;
+    fn set_auto_switch(&mut self, enabled: bool) -> Result<(), String>;
+    fn get_aspm(&mut self) -> Result<(String, Vec<LinkState>), String>;
+    fn get_limits(&mut self) -> Result<Vec<LimitEntry>, String>;
+    fn set_limit(&mut self, limit: &str, value: u8) -> Result<(), String>;
+    fn reset_limits(&mut self) -> Result<(), String>;
+    fn get_powercap(&mut self) -> Result<Vec<ConstraintEntry>, String>;
+    fn get_thermald(&mut self) -> Result<String, String>;
     fn get_switchable(&mut self) -> Result<bool, String>;
     fn set_graphics(&mut self, vendor: &str) -> Result<(), String>;
     fn get_graphics_power(&mut self) -> Result<bool, String>;
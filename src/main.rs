@@ -7,10 +7,11 @@
 use clap::Parser;
 use log::LevelFilter;
 use std::process;
-use system76_power::{args::Args, client, daemon, logging};
+use system76_power::{args::Args, client, daemon, logging, sysroot};
 
 fn main() {
     let args = Args::parse();
+    sysroot::from_env();
 
     let res = match args {
         Args::Daemon { quiet, verbose } => {
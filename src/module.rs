@@ -2,6 +2,7 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
+use crate::sysroot;
 use std::{fs::read_to_string, io};
 
 pub struct Module {
@@ -10,7 +11,7 @@ pub struct Module {
 
 impl Module {
     pub fn all() -> io::Result<Vec<Module>> {
-        read_to_string("/proc/modules")?.lines().map(parse).collect()
+        read_to_string(sysroot::path("/proc/modules"))?.lines().map(parse).collect()
     }
 }
 
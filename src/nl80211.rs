@@ -0,0 +1,269 @@
+// Copyright 2022 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! The power save mode of wireless interfaces, through the nl80211 generic netlink family.
+//!
+//! This works with every driver which implements cfg80211, and takes effect without
+//! disconnecting, unlike module parameters which require the driver to be reloaded.
+
+use std::{
+    convert::TryInto,
+    ffi::CString,
+    io, mem,
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+};
+
+const NLMSG_HDRLEN: usize = 16;
+const GENL_HDRLEN: usize = 4;
+const NLA_HDRLEN: usize = 4;
+
+const NLMSG_ERROR: u16 = 2;
+const NLM_F_REQUEST: u16 = 1;
+const NLM_F_ACK: u16 = 4;
+
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+
+const NL80211_CMD_SET_POWER_SAVE: u8 = 61;
+const NL80211_CMD_GET_POWER_SAVE: u8 = 62;
+const NL80211_ATTR_IFINDEX: u16 = 3;
+const NL80211_ATTR_PS_STATE: u16 = 93;
+
+/// Whether power saving is enabled on a wireless interface.
+pub fn power_save(interface: &str) -> io::Result<bool> {
+    let mut socket = Socket::new()?;
+    let family = socket.family("nl80211")?;
+    let ifindex = ifindex(interface)?;
+
+    let reply = socket.request(
+        family,
+        NL80211_CMD_GET_POWER_SAVE,
+        &[(NL80211_ATTR_IFINDEX, &ifindex.to_ne_bytes())],
+    )?;
+
+    match attribute(&reply, NL80211_ATTR_PS_STATE) {
+        Some(state) if state.len() >= 4 => {
+            Ok(u32::from_ne_bytes(state[..4].try_into().unwrap()) != 0)
+        }
+        _ => Err(invalid("power save state missing from nl80211 reply")),
+    }
+}
+
+/// Enables or disables power saving on a wireless interface.
+pub fn set_power_save(interface: &str, enabled: bool) -> io::Result<()> {
+    let mut socket = Socket::new()?;
+    let family = socket.family("nl80211")?;
+    let ifindex = ifindex(interface)?;
+
+    socket
+        .request(
+            family,
+            NL80211_CMD_SET_POWER_SAVE,
+            &[
+                (NL80211_ATTR_IFINDEX, &ifindex.to_ne_bytes()),
+                (NL80211_ATTR_PS_STATE, &u32::from(enabled).to_ne_bytes()),
+            ],
+        )
+        .map(|_| ())
+}
+
+/// Whether an error means that power save is unsupported through nl80211, by the kernel or by
+/// the driver of the interface, rather than that the request failed.
+pub fn is_unsupported(why: &io::Error) -> bool {
+    why.kind() == io::ErrorKind::Unsupported || why.raw_os_error() == Some(libc::EOPNOTSUPP)
+}
+
+fn ifindex(interface: &str) -> io::Result<u32> {
+    let name = CString::new(interface).map_err(|_| invalid("interface name contains a nul"))?;
+    match unsafe { libc::if_nametoindex(name.as_ptr()) } {
+        0 => Err(io::Error::last_os_error()),
+        index => Ok(index),
+    }
+}
+
+fn invalid(message: &str) -> io::Error { io::Error::new(io::ErrorKind::InvalidData, message) }
+
+/// A generic netlink socket.
+struct Socket {
+    file: std::fs::File,
+    seq:  u32,
+}
+
+impl AsRawFd for Socket {
+    fn as_raw_fd(&self) -> RawFd { self.file.as_raw_fd() }
+}
+
+impl Socket {
+    fn new() -> io::Result<Self> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                libc::NETLINK_GENERIC,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // Owning the descriptor closes it, even if binding fails.
+        let file = unsafe { std::fs::File::from_raw_fd(fd) };
+
+        let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        let result = unsafe {
+            libc::bind(
+                fd,
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { file, seq: 0 })
+    }
+
+    /// Looks up the ID of a generic netlink family by its name.
+    fn family(&mut self, name: &str) -> io::Result<u16> {
+        let mut name = name.as_bytes().to_vec();
+        name.push(0);
+
+        let unavailable = || io::Error::new(io::ErrorKind::Unsupported, "nl80211 is not available");
+        let reply =
+            match self.request(GENL_ID_CTRL, CTRL_CMD_GETFAMILY, &[(CTRL_ATTR_FAMILY_NAME, &name)])
+            {
+                Err(why) if why.raw_os_error() == Some(libc::ENOENT) => return Err(unavailable()),
+                result => result?,
+            };
+
+        match attribute(&reply, CTRL_ATTR_FAMILY_ID) {
+            Some(id) if id.len() >= 2 => Ok(u16::from_ne_bytes([id[0], id[1]])),
+            _ => Err(unavailable()),
+        }
+    }
+
+    /// Sends a request and waits for its acknowledgement, returning the attributes of the reply
+    /// which preceded it, if any.
+    fn request(
+        &mut self,
+        family: u16,
+        cmd: u8,
+        attributes: &[(u16, &[u8])],
+    ) -> io::Result<Vec<u8>> {
+        self.seq += 1;
+
+        let mut message = vec![0; NLMSG_HDRLEN];
+        message.extend_from_slice(&[cmd, 1, 0, 0]);
+        for &(kind, payload) in attributes {
+            message.extend_from_slice(&((NLA_HDRLEN + payload.len()) as u16).to_ne_bytes());
+            message.extend_from_slice(&kind.to_ne_bytes());
+            message.extend_from_slice(payload);
+            message.resize(align(message.len()), 0);
+        }
+
+        let len = message.len() as u32;
+        message[0..4].copy_from_slice(&len.to_ne_bytes());
+        message[4..6].copy_from_slice(&family.to_ne_bytes());
+        message[6..8].copy_from_slice(&(NLM_F_REQUEST | NLM_F_ACK).to_ne_bytes());
+        message[8..12].copy_from_slice(&self.seq.to_ne_bytes());
+
+        let sent = unsafe {
+            libc::send(self.as_raw_fd(), message.as_ptr() as *const libc::c_void, message.len(), 0)
+        };
+        if sent < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut reply = Vec::new();
+        let mut buffer = vec![0u8; 8192];
+        loop {
+            let received = unsafe {
+                libc::recv(
+                    self.as_raw_fd(),
+                    buffer.as_mut_ptr() as *mut libc::c_void,
+                    buffer.len(),
+                    0,
+                )
+            };
+            if received < 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut messages = &buffer[..received as usize];
+            while messages.len() >= NLMSG_HDRLEN {
+                let len = u32::from_ne_bytes(messages[0..4].try_into().unwrap()) as usize;
+                let kind = u16::from_ne_bytes([messages[4], messages[5]]);
+                let seq = u32::from_ne_bytes(messages[8..12].try_into().unwrap());
+                if len < NLMSG_HDRLEN || len > messages.len() {
+                    return Err(invalid("truncated netlink message"));
+                }
+
+                let payload = &messages[NLMSG_HDRLEN..len];
+                if seq == self.seq {
+                    if kind == NLMSG_ERROR {
+                        if payload.len() < 4 {
+                            return Err(invalid("truncated netlink error"));
+                        }
+
+                        // An error code of zero acknowledges the request.
+                        return match i32::from_ne_bytes(payload[0..4].try_into().unwrap()) {
+                            0 => Ok(reply),
+                            error => Err(io::Error::from_raw_os_error(-error)),
+                        };
+                    } else if payload.len() >= GENL_HDRLEN {
+                        reply.extend_from_slice(&payload[GENL_HDRLEN..]);
+                    }
+                }
+
+                messages = &messages[align(len).min(messages.len())..];
+            }
+        }
+    }
+}
+
+/// Rounds a length up to the 4-byte alignment of netlink messages and attributes.
+fn align(len: usize) -> usize { (len + 3) & !3 }
+
+/// Finds the payload of an attribute in a sequence of attributes.
+fn attribute(mut attributes: &[u8], kind: u16) -> Option<&[u8]> {
+    while attributes.len() >= NLA_HDRLEN {
+        let len = u16::from_ne_bytes([attributes[0], attributes[1]]) as usize;
+        if len < NLA_HDRLEN || len > attributes.len() {
+            return None;
+        }
+
+        // The upper bits of the type are flags.
+        if u16::from_ne_bytes([attributes[2], attributes[3]]) & 0x3FFF == kind {
+            return Some(&attributes[NLA_HDRLEN..len]);
+        }
+
+        attributes = &attributes[align(len).min(attributes.len())..];
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attributes_are_found_past_padding() {
+        let mut attributes = Vec::new();
+        attributes.extend_from_slice(&6u16.to_ne_bytes());
+        attributes.extend_from_slice(&CTRL_ATTR_FAMILY_ID.to_ne_bytes());
+        attributes.extend_from_slice(&[0x1c, 0x00, 0, 0]);
+        attributes.extend_from_slice(&8u16.to_ne_bytes());
+        attributes.extend_from_slice(&(NL80211_ATTR_PS_STATE | 0x8000).to_ne_bytes());
+        attributes.extend_from_slice(&1u32.to_ne_bytes());
+
+        assert_eq!(attribute(&attributes, CTRL_ATTR_FAMILY_ID), Some(&[0x1c, 0x00][..]));
+        assert_eq!(attribute(&attributes, NL80211_ATTR_PS_STATE), Some(&1u32.to_ne_bytes()[..]));
+        assert_eq!(attribute(&attributes, NL80211_ATTR_IFINDEX), None);
+    }
+}
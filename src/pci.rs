@@ -2,6 +2,7 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
+use crate::sysroot;
 use std::{fs::write, io, path::PathBuf};
 
 pub struct PciBus {
@@ -10,7 +11,7 @@ pub struct PciBus {
 
 impl PciBus {
     pub fn new() -> io::Result<PciBus> {
-        let path = PathBuf::from("/sys/bus/pci");
+        let path = sysroot::path("/sys/bus/pci");
         if path.is_dir() {
             Ok(PciBus { path })
         } else {
@@ -0,0 +1,334 @@
+// Copyright 2022 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Changes made to the system when settings such as a power profile are applied.
+//!
+//! Setters describe each change to a [`Plan`], which performs it right away, or only records it
+//! when the plan is a dry run so that the changes can be reviewed without touching the system.
+
+use crate::nl80211;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt, fs,
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+};
+
+/// A single change, in the order in which it is performed.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "operation", rename_all = "lowercase")]
+pub enum Operation {
+    /// Writes a value to a file, such as a sysfs attribute.
+    Write { path: PathBuf, old: Option<String>, new: String },
+    /// Replaces a bit field of a 64-bit register, such as a model-specific register.
+    Register {
+        path:     PathBuf,
+        register: u64,
+        shift:    u32,
+        width:    u32,
+        old:      Option<u64>,
+        new:      u64,
+    },
+    /// Runs a command.
+    Command { command: String, args: Vec<String> },
+    /// Enables or disables power saving on a wireless interface through nl80211.
+    #[serde(rename = "wifi_power_save")]
+    WifiPowerSave { interface: String, old: Option<bool>, new: bool },
+    /// Starts or stops a systemd unit.
+    Unit { unit: String, old: Option<bool>, new: bool },
+}
+
+impl Operation {
+    /// The operation as a `(path, old value, new value, command)` tuple of strings, which are
+    /// empty where they do not apply.
+    pub fn to_tuple(&self) -> (String, String, String, String) {
+        match self {
+            Operation::Write { path, old, new } => (
+                path.display().to_string(),
+                old.clone().unwrap_or_default(),
+                new.clone(),
+                String::new(),
+            ),
+            Operation::Register { path, register, shift, width, old, new } => (
+                format!("{}@{:#x}[{}..{}]", path.display(), register, shift, shift + width),
+                old.map(|old| old.to_string()).unwrap_or_default(),
+                new.to_string(),
+                String::new(),
+            ),
+            Operation::Command { command, args } => {
+                let mut command = command.clone();
+                for arg in args {
+                    command.push(' ');
+                    command.push_str(arg);
+                }
+                (String::new(), String::new(), String::new(), command)
+            }
+            Operation::WifiPowerSave { interface, old, new } => (
+                format!("nl80211:{}/power_save", interface),
+                old.map(on_off).unwrap_or_default(),
+                on_off(*new),
+                String::new(),
+            ),
+            Operation::Unit { unit, old, new } => (
+                format!("systemd:{}", unit),
+                old.map(active_inactive).unwrap_or_default(),
+                active_inactive(*new),
+                String::new(),
+            ),
+        }
+    }
+}
+
+fn on_off(enabled: bool) -> String { if enabled { "on" } else { "off" }.to_owned() }
+
+fn active_inactive(active: bool) -> String { if active { "active" } else { "inactive" }.to_owned() }
+
+/// Why an operation failed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Failure {
+    pub kind:    io::ErrorKind,
+    pub message: String,
+}
+
+impl From<&io::Error> for Failure {
+    fn from(why: &io::Error) -> Self { Self { kind: why.kind(), message: why.to_string() } }
+}
+
+/// A `(setting, target, requested value, result, error kind)` entry of an apply report.
+pub type ReportEntry = (String, String, String, String, String);
+
+/// An operation, the setting which it applies, and whether it succeeded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub setting:   &'static str,
+    pub operation: Operation,
+    pub failure:   Option<Failure>,
+    /// The operation is performed later, such as a unit change, and its result is not known yet.
+    pub pending:   bool,
+}
+
+impl Step {
+    /// The step as a report entry, where the target is the file written or the command run, and
+    /// the result is `ok`, `pending` or the error message.
+    pub fn to_report(&self) -> ReportEntry {
+        let (path, _old, new, command) = self.operation.to_tuple();
+        let target = if command.is_empty() { path } else { command };
+        let (result, kind) = match self.failure {
+            Some(ref failure) => (failure.message.clone(), format!("{:?}", failure.kind)),
+            None if self.pending => ("pending".to_owned(), String::new()),
+            None => ("ok".to_owned(), String::new()),
+        };
+
+        (self.setting.to_owned(), target, new, result, kind)
+    }
+}
+
+/// The operations performed, or to be performed, by a setter.
+#[derive(Debug, Default)]
+pub struct Plan {
+    dry_run: bool,
+    setting: &'static str,
+    steps:   Vec<Step>,
+}
+
+impl Plan {
+    /// A plan which performs each operation as it is added.
+    pub fn new() -> Self { Self::default() }
+
+    /// A plan which only records its operations.
+    pub fn dry_run() -> Self { Self { dry_run: true, ..Self::default() } }
+
+    pub fn is_dry_run(&self) -> bool { self.dry_run }
+
+    /// Attributes the operations which are added from now on to the given setting.
+    pub fn setting(&mut self, setting: &'static str) { self.setting = setting; }
+
+    pub fn operations(&self) -> impl Iterator<Item = &Operation> {
+        self.steps.iter().map(|step| &step.operation)
+    }
+
+    pub fn steps(&self) -> &[Step] { &self.steps }
+
+    pub fn into_steps(self) -> Vec<Step> { self.steps }
+
+    fn push<T>(&mut self, operation: Operation, result: io::Result<T>) -> io::Result<T> {
+        let failure = result.as_ref().err().map(Failure::from);
+        self.steps.push(Step { setting: self.setting, operation, failure, pending: false });
+        result
+    }
+
+    /// Writes `value` to the file at `path`.
+    pub fn write<P: AsRef<Path>, V: fmt::Display>(&mut self, path: P, value: V) -> io::Result<()> {
+        let path = path.as_ref();
+        let new = value.to_string();
+        let old = fs::read_to_string(path).ok().map(|old| old.trim_end().to_owned());
+
+        log::debug!("writing {} to {}", new, path.display());
+        let result = if self.dry_run { Ok(()) } else { fs::write(path, &new) };
+
+        self.push(Operation::Write { path: path.to_owned(), old, new }, result)
+    }
+
+    /// Sets the `width` bits starting at bit `shift` of the 64-bit register at offset `register`
+    /// of the file at `path` to `value`, keeping the other bits.
+    pub fn write_register<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        register: u64,
+        shift: u32,
+        width: u32,
+        value: u64,
+    ) -> io::Result<()> {
+        let path = path.as_ref();
+        let mask = (u64::MAX >> (64 - width)) << shift;
+        let current = read_register(path, register);
+        let old = current.as_ref().ok().map(|current| (current & mask) >> shift);
+
+        let result = if self.dry_run {
+            Ok(())
+        } else {
+            current.and_then(|current| {
+                let data = (current & !mask) | ((value << shift) & mask);
+                let mut file = fs::OpenOptions::new().write(true).open(path)?;
+                file.seek(SeekFrom::Start(register))?;
+                file.write_all(&data.to_le_bytes())
+            })
+        };
+
+        let operation =
+            Operation::Register { path: path.to_owned(), register, shift, width, old, new: value };
+        self.push(operation, result)
+    }
+
+    /// Enables or disables power saving on a wireless interface.
+    pub fn set_wifi_power_save(&mut self, interface: &str, enabled: bool) -> io::Result<()> {
+        let old = nl80211::power_save(interface).ok();
+
+        log::debug!("setting power save of {} to {}", interface, on_off(enabled));
+        let result =
+            if self.dry_run { Ok(()) } else { nl80211::set_power_save(interface, enabled) };
+
+        let operation =
+            Operation::WifiPowerSave { interface: interface.to_owned(), old, new: enabled };
+        self.push(operation, result)
+    }
+
+    /// Starts or stops a systemd unit, whose last known state is `old`. The unit is managed
+    /// through the D-Bus connection of the daemon, so the change is only recorded here as
+    /// pending, and performed by the daemon once it takes the [`units`](Self::units) of the plan.
+    pub fn set_unit_active(&mut self, unit: &str, active: bool, old: Option<bool>) {
+        log::debug!("setting {} to {}", unit, active_inactive(active));
+        self.steps.push(Step {
+            setting:   self.setting,
+            operation: Operation::Unit { unit: unit.to_owned(), old, new: active },
+            failure:   None,
+            pending:   !self.dry_run,
+        });
+    }
+
+    /// The systemd units which the plan starts or stops, none of which a dry run touches.
+    pub fn units(&self) -> Vec<(String, bool)> {
+        if self.dry_run {
+            return Vec::new();
+        }
+
+        self.operations()
+            .filter_map(|operation| match operation {
+                Operation::Unit { unit, new, .. } => Some((unit.clone(), *new)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Runs `command` with `args`, returning its exit status. A dry run reports success. The
+    /// step fails if the command does, although its status is still returned.
+    pub fn run(&mut self, command: &str, args: &[&str]) -> io::Result<ExitStatus> {
+        let result = if self.dry_run {
+            Ok(ExitStatus::from_raw(0))
+        } else {
+            Command::new(command).args(args).stdout(Stdio::null()).status()
+        };
+
+        let operation = Operation::Command {
+            command: command.to_owned(),
+            args:    args.iter().map(|&arg| arg.to_owned()).collect(),
+        };
+
+        match result {
+            Ok(status) if !status.success() => {
+                let message = format!("{} exited with {}", command, status);
+                let failure = Failure { kind: io::ErrorKind::Other, message };
+                let failure = Some(failure);
+                self.steps.push(Step { setting: self.setting, operation, failure, pending: false });
+                Ok(status)
+            }
+            result => self.push(operation, result),
+        }
+    }
+}
+
+/// Reads the 64-bit register at offset `register` of the file at `path`.
+pub fn read_register(path: &Path, register: u64) -> io::Result<u64> {
+    let mut file = fs::File::open(path)?;
+    file.seek(SeekFrom::Start(register))?;
+    let mut data = [0; 8];
+    file.read_exact(&mut data)?;
+    Ok(u64::from_le_bytes(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dry_run_records_without_writing() {
+        let path = std::env::temp_dir().join(format!("system76-power-plan-{}", std::process::id()));
+        fs::write(&path, "1\n").unwrap();
+
+        let mut plan = Plan::dry_run();
+        plan.write(&path, 5).unwrap();
+        plan.run("systemctl", &["stop", "thermald.service"]).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "1\n");
+        assert_eq!(
+            plan.operations().cloned().collect::<Vec<_>>(),
+            vec![
+                Operation::Write { path: path.clone(), old: Some("1".into()), new: "5".into() },
+                Operation::Command {
+                    command: "systemctl".into(),
+                    args:    vec!["stop".into(), "thermald.service".into()],
+                },
+            ]
+        );
+
+        let mut plan = Plan::new();
+        plan.setting("laptop_mode");
+        plan.write(&path, 5).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "5");
+
+        fs::remove_file(&path).unwrap();
+        let missing = path.join("missing");
+        assert!(plan.write(&missing, 2).is_err());
+
+        let report: Vec<_> = plan.steps().iter().map(Step::to_report).collect();
+        assert_eq!(
+            report[0],
+            (
+                "laptop_mode".into(),
+                path.display().to_string(),
+                "5".into(),
+                "ok".into(),
+                String::new()
+            )
+        );
+        assert_eq!(report[1].3, plan.steps()[1].failure.as_ref().unwrap().message);
+        assert_eq!(report[1].4, "NotFound");
+
+        // A command which fails is reported, but its status is still returned.
+        assert!(!plan.run("false", &[]).unwrap().success());
+        assert_eq!(plan.steps()[2].failure.as_ref().unwrap().kind, io::ErrorKind::Other);
+    }
+}
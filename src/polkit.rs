@@ -16,22 +16,9 @@ type Details<'l> = HashMap<&'l str, &'l str>;
 
 const ALLOW_USER_INTERACTION: u32 = 1;
 
-pub(crate) async fn get_connection_unix_process_id(
+async fn check_authorization(
     c: &SyncConnection,
-    sender: BusName<'_>,
-) -> Result<u32, dbus::Error> {
-    let proxy =
-        Proxy::new("org.freedesktop.DBus", "/org/freedesktop/DBus", Duration::new(25, 0), c);
-    let (pid,) = proxy
-        .method_call("org.freedesktop.DBus", "GetConnectionUnixProcessID", (sender.to_string(),))
-        .await?;
-    Ok(pid)
-}
-
-pub(crate) async fn check_authorization(
-    c: &SyncConnection,
-    pid: u32,
-    start_time: u64,
+    subject: Subject<'_>,
     action_id: &str,
 ) -> Result<bool, dbus::Error> {
     let proxy = Proxy::new(
@@ -41,14 +28,21 @@ pub(crate) async fn check_authorization(
         c,
     );
 
-    let mut subject_details = SubjectDetails::new();
-    subject_details.insert("pid", Variant(Box::new(pid)));
-    subject_details.insert("start-time", Variant(Box::new(start_time)));
-    let subject: Subject = ("unix-process", subject_details);
-
     let args = (subject, action_id, Details::new(), ALLOW_USER_INTERACTION, "");
     let ((is_authorized, _is_challenge, _details),): (AuthorizationResult,) = proxy
         .method_call("org.freedesktop.PolicyKit1.Authority", "CheckAuthorization", args)
         .await?;
     Ok(is_authorized)
 }
+
+/// Whether the sender of a message is authorized to perform an action. The sender is identified
+/// by its unique bus name, which, unlike its process ID, is never reused by another process.
+pub(crate) async fn is_sender_authorized(
+    c: &SyncConnection,
+    sender: BusName<'_>,
+    action_id: &str,
+) -> Result<bool, dbus::Error> {
+    let mut subject_details = SubjectDetails::new();
+    subject_details.insert("name", Variant(Box::new(sender.to_string())));
+    check_authorization(c, ("system-bus-name", subject_details), action_id).await
+}
@@ -0,0 +1,318 @@
+// Copyright 2022 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Power limits through the powercap framework, which exposes the RAPL domains of Intel and AMD
+//! CPUs as zones, each with constraints on its average power over a time window.
+//!
+//! Every zone is listed in [`POWERCAP_PATH`], whatever its control type: packages such as
+//! `intel-rapl:0` on each socket, their subzones such as `intel-rapl:0:0` for the cores, the
+//! same packages through `intel-rapl-mmio`, and the zones of `amd-rapl` where it is present.
+
+use crate::{errors::PowercapError, plan::Plan, sysroot};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+pub const POWERCAP_PATH: &str = "/sys/class/powercap";
+
+/// The constraint of PL1, which limits the average power over a long time window.
+pub const LONG_TERM: &str = "long_term";
+/// The constraint of PL2, which limits the average power over a short time window.
+pub const SHORT_TERM: &str = "short_term";
+/// The constraint of PL4, which limits the instantaneous power.
+pub const PEAK_POWER: &str = "peak_power";
+
+/// A `(zone, name, constraint, power limit in µW, time window in µs, maximum power in µW)`
+/// entry, where values which the zone does not have are zero.
+pub type ConstraintEntry = (String, String, String, u64, u64, u64);
+
+/// A limit set on a constraint by a profile. Values which are omitted are left as they are.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConstraintLimit {
+    pub power_limit_w:  Option<u32>,
+    pub time_window_us: Option<u64>,
+}
+
+/// A zone, which is a package or one of its domains, such as its cores, uncore or memory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Zone {
+    /// The name of the directory of the zone, such as `intel-rapl:0:1`.
+    pub id:          String,
+    /// The name of the domain, such as `package-0`, `core`, `dram` or `psys`.
+    pub name:        String,
+    pub path:        PathBuf,
+    pub constraints: Vec<Constraint>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Constraint {
+    pub index:          u32,
+    /// One of [`LONG_TERM`], [`SHORT_TERM`] or [`PEAK_POWER`].
+    pub name:           String,
+    pub power_limit_uw: Option<u64>,
+    pub time_window_us: Option<u64>,
+    pub max_power_uw:   Option<u64>,
+}
+
+impl Zone {
+    /// Reads the zone at `path`, or `None` if it is not a zone, such as the directory of a
+    /// control type.
+    fn read(path: PathBuf) -> Result<Option<Self>, PowercapError> {
+        let name = match read_optional(&path.join("name"))? {
+            Some(name) => name,
+            None => return Ok(None),
+        };
+
+        let mut constraints = Vec::new();
+        for index in 0.. {
+            let attribute = |suffix: &str| path.join(format!("constraint_{}_{}", index, suffix));
+            let name = match read_optional(&attribute("name"))? {
+                Some(name) => name,
+                None => break,
+            };
+
+            constraints.push(Constraint {
+                index,
+                name,
+                power_limit_uw: read_number(&attribute("power_limit_uw")),
+                time_window_us: read_number(&attribute("time_window_us")),
+                max_power_uw: read_number(&attribute("max_power_uw")),
+            });
+        }
+
+        let id = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        Ok(Some(Self { id, name, path, constraints }))
+    }
+
+    /// Whether the zone is a whole package, rather than one of its domains.
+    pub fn is_package(&self) -> bool { self.name.starts_with("package-") }
+
+    /// Whether a key of a profile definition selects the zone. That is its ID, its name, or its
+    /// name without the number of the socket, so that `package` selects every `package-N`.
+    pub fn matches(&self, key: &str) -> bool {
+        key == self.id
+            || key == self.name
+            || self.name.rsplit_once('-').map_or(false, |(base, socket)| {
+                key == base && socket.bytes().all(|byte| byte.is_ascii_digit())
+            })
+    }
+
+    pub fn constraint(&self, name: &str) -> Option<&Constraint> {
+        self.constraints.iter().find(|constraint| constraint.name == name)
+    }
+
+    /// Sets the power limit, and the time window, of a constraint of the zone.
+    pub fn set(
+        &self,
+        plan: &mut Plan,
+        constraint: &str,
+        limit: &ConstraintLimit,
+    ) -> Result<(), PowercapError> {
+        let index = match self.constraint(constraint) {
+            Some(constraint) => constraint.index,
+            None => {
+                return Err(PowercapError::NoConstraint {
+                    zone:       self.id.clone(),
+                    constraint: constraint.to_owned(),
+                })
+            }
+        };
+
+        let write = |plan: &mut Plan, suffix: &str, value: u64| {
+            let path = self.path.join(format!("constraint_{}_{}", index, suffix));
+            plan.write(&path, value).map_err(|why| PowercapError::Write(path, why))
+        };
+
+        if let Some(watts) = limit.power_limit_w {
+            write(plan, "power_limit_uw", u64::from(watts) * 1_000_000)?;
+        }
+
+        if let Some(microseconds) = limit.time_window_us {
+            write(plan, "time_window_us", microseconds)?;
+        }
+
+        Ok(())
+    }
+
+    /// The constraints of the zone as entries.
+    pub fn to_entries(&self) -> impl Iterator<Item = ConstraintEntry> + '_ {
+        self.constraints.iter().map(move |constraint| {
+            (
+                self.id.clone(),
+                self.name.clone(),
+                constraint.name.clone(),
+                constraint.power_limit_uw.unwrap_or(0),
+                constraint.time_window_us.unwrap_or(0),
+                constraint.max_power_uw.unwrap_or(0),
+            )
+        })
+    }
+}
+
+/// Every zone and subzone, ordered by ID.
+pub fn zones() -> Result<Vec<Zone>, PowercapError> {
+    let dir = sysroot::path(POWERCAP_PATH);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(why) if why.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(why) => return Err(PowercapError::Read(dir, why)),
+    };
+
+    let mut zones = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|why| PowercapError::Read(dir.clone(), why))?;
+        if let Some(zone) = Zone::read(entry.path())? {
+            zones.push(zone);
+        }
+    }
+
+    zones.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(zones)
+}
+
+/// Sets the limits of a profile, which are keyed by zone as described by [`Zone::matches`], and
+/// then by constraint. Every key must select at least one zone.
+pub fn set(
+    plan: &mut Plan,
+    limits: &BTreeMap<String, BTreeMap<String, ConstraintLimit>>,
+) -> Result<(), PowercapError> {
+    if limits.is_empty() {
+        return Ok(());
+    }
+
+    let zones = zones()?;
+    let mut result = Ok(());
+    for (key, constraints) in limits {
+        let mut selected = zones.iter().filter(|zone| zone.matches(key)).peekable();
+        if selected.peek().is_none() {
+            result = Err(PowercapError::NoZone(key.clone()));
+            continue;
+        }
+
+        for zone in selected {
+            for (constraint, limit) in constraints {
+                if let Err(why) = zone.set(plan, constraint, limit) {
+                    result = Err(why);
+                }
+            }
+        }
+    }
+
+    result
+}
+
+/// Reads a trimmed attribute, or `None` if the file is missing.
+fn read_optional(path: &Path) -> Result<Option<String>, PowercapError> {
+    match fs::read_to_string(path) {
+        Ok(value) => Ok(Some(value.trim().to_owned())),
+        Err(why) if why.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(why) => Err(PowercapError::Read(path.to_owned(), why)),
+    }
+}
+
+/// Reads a numeric attribute, or `None` if it is missing or cannot be read. Attributes such as
+/// `constraint_N_max_power_uw` fail with `ENODATA` or `EOPNOTSUPP` on some firmware.
+fn read_number(path: &Path) -> Option<u64> {
+    let value = match fs::read_to_string(path) {
+        Ok(value) => value,
+        Err(why) => {
+            if why.kind() != io::ErrorKind::NotFound {
+                log::debug!("failed to read {}: {}", path.display(), why);
+            }
+            return None;
+        }
+    };
+
+    match value.trim().parse() {
+        Ok(number) => Some(number),
+        Err(_) => {
+            log::warn!("{} is not a number: '{}'", path.display(), value.trim());
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysroot::FakeRoot;
+
+    fn zone(root: &FakeRoot, id: &str, name: &str, constraints: &[&str]) {
+        let dir = format!("{}/{}/", POWERCAP_PATH, id);
+        root.write(&[&dir, "name"].concat(), &format!("{}\n", name));
+        for (index, constraint) in constraints.iter().enumerate() {
+            let attribute = |suffix: &str| format!("{}constraint_{}_{}", dir, index, suffix);
+            root.write(&attribute("name"), &format!("{}\n", constraint));
+            root.write(&attribute("power_limit_uw"), "15000000\n");
+            if *constraint != PEAK_POWER {
+                root.write(&attribute("time_window_us"), "27983872\n");
+            }
+        }
+    }
+
+    #[test]
+    fn limits_apply_to_matching_zones() {
+        let root = FakeRoot::new("powercap");
+        root.write(&format!("{}/intel-rapl/enabled", POWERCAP_PATH), "1\n");
+        zone(&root, "intel-rapl:0", "package-0", &[LONG_TERM, SHORT_TERM, PEAK_POWER]);
+        zone(&root, "intel-rapl:0:0", "core", &[LONG_TERM]);
+        zone(&root, "intel-rapl:1", "package-1", &[LONG_TERM, SHORT_TERM]);
+
+        let zones = zones().unwrap();
+        assert_eq!(
+            zones.iter().map(|zone| zone.id.as_str()).collect::<Vec<_>>(),
+            vec!["intel-rapl:0", "intel-rapl:0:0", "intel-rapl:1"]
+        );
+        assert!(zones[0].is_package() && !zones[1].is_package());
+        assert_eq!(zones[0].constraint(PEAK_POWER).unwrap().time_window_us, None);
+
+        let limit =
+            |watts, window| ConstraintLimit { power_limit_w: Some(watts), time_window_us: window };
+        let mut limits = BTreeMap::new();
+        limits.insert("package".to_owned(), BTreeMap::new());
+        limits
+            .get_mut("package")
+            .unwrap()
+            .insert(LONG_TERM.to_owned(), limit(45, Some(28_000_000)));
+        limits.insert("intel-rapl:0".to_owned(), BTreeMap::new());
+        limits.get_mut("intel-rapl:0").unwrap().insert(PEAK_POWER.to_owned(), limit(120, None));
+
+        set(&mut Plan::new(), &limits).unwrap();
+        for id in &["intel-rapl:0", "intel-rapl:1"] {
+            let dir = format!("{}/{}/", POWERCAP_PATH, id);
+            assert_eq!(root.read(&[&dir, "constraint_0_power_limit_uw"].concat()), "45000000");
+            assert_eq!(root.read(&[&dir, "constraint_0_time_window_us"].concat()), "28000000");
+        }
+        assert_eq!(
+            root.read(&format!("{}/intel-rapl:0/constraint_2_power_limit_uw", POWERCAP_PATH)),
+            "120000000"
+        );
+        assert_eq!(
+            root.read(&format!("{}/intel-rapl:0:0/constraint_0_power_limit_uw", POWERCAP_PATH)),
+            "15000000\n"
+        );
+
+        limits.insert("dram".to_owned(), BTreeMap::new());
+        assert!(matches!(set(&mut Plan::new(), &limits), Err(PowercapError::NoZone(_))));
+    }
+
+    #[test]
+    fn unreadable_attributes_are_absent() {
+        let root = FakeRoot::new("powercap-unreadable");
+        zone(&root, "intel-rapl:0", "package-0", &[LONG_TERM]);
+
+        // A directory fails to be read as a file, as the attribute fails with ENODATA.
+        let max_power = format!("{}/intel-rapl:0/constraint_0_max_power_uw", POWERCAP_PATH);
+        fs::create_dir_all(sysroot::path(&max_power)).unwrap();
+
+        let zones = zones().unwrap();
+        let constraint = zones[0].constraint(LONG_TERM).unwrap();
+        assert_eq!(constraint.power_limit_uw, Some(15_000_000));
+        assert_eq!(constraint.max_power_uw, None);
+    }
+}
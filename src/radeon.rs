@@ -2,7 +2,7 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
-use crate::kernel_parameters::*;
+use crate::{kernel_parameters::*, plan::Plan};
 
 pub struct RadeonDevice {
     card:                      u8,
@@ -37,7 +37,13 @@ impl RadeonDevice {
         }
     }
 
-    pub fn set_profiles(&self, power_profile: &str, dpm_state: &str, dpm_perf: &str) {
+    pub fn set_profiles(
+        &self,
+        plan: &mut Plan,
+        power_profile: &str,
+        dpm_state: &str,
+        dpm_perf: &str,
+    ) {
         log::debug!(
             "Setting radeon{} to power profile {}; DPM state {}; DPM perf {}",
             self.card,
@@ -45,10 +51,10 @@ impl RadeonDevice {
             dpm_state,
             dpm_perf
         );
-        self.dpm_state.set(dpm_state.as_bytes());
-        self.dpm_force_performance.set(dpm_perf.as_bytes());
-        self.power_method.set(b"profile");
-        self.power_profile.set(power_profile.as_bytes());
+        self.dpm_state.set_with(plan, dpm_state);
+        self.dpm_force_performance.set_with(plan, dpm_perf);
+        self.power_method.set_with(plan, "profile");
+        self.power_profile.set_with(plan, power_profile);
     }
 }
 
@@ -2,8 +2,7 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
-use crate::kernel_parameters::*;
-use std::path::Path;
+use crate::{kernel_parameters::*, plan::Plan, sysroot};
 
 pub struct SoundDevice {
     device:                &'static str,
@@ -13,7 +12,7 @@ pub struct SoundDevice {
 
 impl SoundDevice {
     pub fn new(device: &'static str) -> Option<SoundDevice> {
-        if !Path::new(&["/sys/module/", device].concat()).exists() {
+        if !sysroot::path(["/sys/module/", device].concat()).exists() {
             return None;
         }
 
@@ -29,7 +28,7 @@ impl SoundDevice {
         })
     }
 
-    pub fn set_power_save(&self, timeout: u32, enable_controller: bool) {
+    pub fn set_power_save(&self, plan: &mut Plan, timeout: u32, enable_controller: bool) {
         log::debug!(
             "{} power controller for {}, with power save timeout value of {}",
             if enable_controller { "Enabling" } else { "Disabling" },
@@ -37,9 +36,9 @@ impl SoundDevice {
             timeout
         );
 
-        self.power_save.set(timeout.to_string().as_bytes());
+        self.power_save.set_with(plan, &timeout.to_string());
         if let Some(ref controller) = self.power_save_controller {
-            controller.set(if enable_controller { b"Y" } else { b"N" });
+            controller.set_with(plan, if enable_controller { "Y" } else { "N" });
         }
     }
 }
@@ -0,0 +1,122 @@
+// Copyright 2022 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! The directory which system paths such as `/sys`, `/proc`, `/dev` and `/etc` are found in.
+//!
+//! This is `/` unless [`ROOT_VAR`] names another directory, which allows the daemon to run
+//! against a synthetic tree, such as one created by a test in a temporary directory.
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    sync::RwLock,
+};
+use sysfs_class::SysClass;
+
+/// The environment variable which overrides the root directory.
+pub const ROOT_VAR: &str = "S76_POWER_ROOT";
+
+static ROOT: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+/// Uses the directory in [`ROOT_VAR`] as the root, if it is set.
+pub fn from_env() {
+    if let Some(root) = env::var_os(ROOT_VAR) {
+        set(Some(PathBuf::from(root)));
+    }
+}
+
+/// Sets the root directory, or restores `/` with `None`.
+pub fn set(root: Option<PathBuf>) { *ROOT.write().unwrap() = root; }
+
+/// Resolves an absolute system path against the root directory.
+pub fn path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+    match *ROOT.read().unwrap() {
+        Some(ref root) => root.join(path.strip_prefix("/").unwrap_or(path)),
+        None => path.to_owned(),
+    }
+}
+
+/// Iterates the devices of a sysfs class found under the root directory.
+pub fn class_iter<T: SysClass>() -> Box<dyn Iterator<Item = io::Result<T>>> {
+    if ROOT.read().unwrap().is_none() {
+        return T::iter();
+    }
+
+    match fs::read_dir(path(T::dir())) {
+        // The directory was found by listing the class, so it is known to belong to it.
+        Ok(entries) => Box::new(
+            entries.map(|entry| entry.map(|entry| unsafe { T::from_path_unchecked(entry.path()) })),
+        ),
+        Err(why) => Box::new(std::iter::once(Err(why))),
+    }
+}
+
+/// Collects the devices of a sysfs class found under the root directory.
+pub fn class_all<T: SysClass>() -> io::Result<Vec<T>> { class_iter().collect() }
+
+/// A synthetic tree which is used as the root directory while it exists.
+#[cfg(test)]
+pub(crate) struct FakeRoot {
+    dir:    PathBuf,
+    _guard: std::sync::MutexGuard<'static, ()>,
+}
+
+#[cfg(test)]
+impl FakeRoot {
+    pub fn new(name: &str) -> Self {
+        // Tests run in parallel, but there is only one root.
+        static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+        let guard = LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        let dir = env::temp_dir().join(format!("system76-power-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        set(Some(dir.clone()));
+
+        Self { dir, _guard: guard }
+    }
+
+    /// Creates a file, and its parent directories, at a system path within the tree.
+    pub fn write(&self, path: &str, contents: &str) {
+        let path = self.dir.join(path.trim_start_matches('/'));
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    /// Reads a file at a system path within the tree.
+    pub fn read(&self, path: &str) -> String {
+        fs::read_to_string(self.dir.join(path.trim_start_matches('/'))).unwrap()
+    }
+}
+
+#[cfg(test)]
+impl Drop for FakeRoot {
+    fn drop(&mut self) {
+        set(None);
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_resolved_against_root() {
+        let root = FakeRoot::new("sysroot");
+        assert_eq!(path("/sys/class/power_supply"), root.dir.join("sys/class/power_supply"));
+        assert_eq!(path("/proc/sys/vm/laptop_mode"), root.dir.join("proc/sys/vm/laptop_mode"));
+
+        root.write("/sys/class/hwmon/hwmon0/name", "coretemp\n");
+        let devices = class_all::<sysfs_class::HwMon>().unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].path(), root.dir.join("sys/class/hwmon/hwmon0"));
+
+        // The root is only replaced while the lock of the fake root is held.
+        set(None);
+        assert_eq!(path("/dev/cpu/0/msr"), Path::new("/dev/cpu/0/msr"));
+        drop(root);
+    }
+}
@@ -0,0 +1,39 @@
+// Copyright 2022 System76 <info@system76.com>
+//
+// SPDX-License-Identifier: GPL-3.0-only
+
+//! Units of the system manager, through the D-Bus API of systemd.
+
+use dbus::nonblock::{stdintf::org_freedesktop_dbus::Properties, Proxy, SyncConnection};
+use std::time::Duration;
+
+const SYSTEMD_NAME: &str = "org.freedesktop.systemd1";
+const SYSTEMD_PATH: &str = "/org/freedesktop/systemd1";
+const MANAGER_IFACE: &str = "org.freedesktop.systemd1.Manager";
+const UNIT_IFACE: &str = "org.freedesktop.systemd1.Unit";
+
+const TIMEOUT: Duration = Duration::from_secs(25);
+
+/// Whether a unit is active, or `None` if it is not installed.
+pub async fn is_active(c: &SyncConnection, unit: &str) -> Result<Option<bool>, dbus::Error> {
+    let manager = Proxy::new(SYSTEMD_NAME, SYSTEMD_PATH, TIMEOUT, c);
+    let (path,): (dbus::Path,) = manager.method_call(MANAGER_IFACE, "LoadUnit", (unit,)).await?;
+
+    let unit = Proxy::new(SYSTEMD_NAME, path, TIMEOUT, c);
+    let load_state: String = unit.get(UNIT_IFACE, "LoadState").await?;
+    if load_state != "loaded" {
+        return Ok(None);
+    }
+
+    let active_state: String = unit.get(UNIT_IFACE, "ActiveState").await?;
+    Ok(Some(matches!(active_state.as_str(), "active" | "activating" | "reloading")))
+}
+
+/// Queues a job which starts or stops a unit.
+pub async fn set_active(c: &SyncConnection, unit: &str, active: bool) -> Result<(), dbus::Error> {
+    let manager = Proxy::new(SYSTEMD_NAME, SYSTEMD_PATH, TIMEOUT, c);
+    let method = if active { "StartUnit" } else { "StopUnit" };
+    let (_job,): (dbus::Path,) =
+        manager.method_call(MANAGER_IFACE, method, (unit, "replace")).await?;
+    Ok(())
+}
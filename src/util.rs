@@ -9,6 +9,19 @@ use std::{
     path::Path,
 };
 
+/// Finds the value in brackets of a sysfs attribute which lists the alternatives, as in
+/// `default performance [powersave] powersupersave`.
+pub fn selected(alternatives: &str) -> Option<&str> {
+    let start = alternatives.find('[')? + 1;
+    let end = start + alternatives[start..].find(']')?;
+    Some(&alternatives[start..end])
+}
+
+/// The alternatives listed by such an attribute, including the selected one.
+pub fn alternatives(alternatives: &str) -> impl Iterator<Item = &str> {
+    alternatives.split_whitespace().map(|value| value.trim_start_matches('[').trim_end_matches(']'))
+}
+
 pub fn entries<T, F: FnMut(DirEntry) -> T>(path: &Path, mut func: F) -> io::Result<Vec<T>> {
     let mut ret = Vec::new();
     for entry_res in path.read_dir()? {
@@ -20,7 +33,6 @@ pub fn entries<T, F: FnMut(DirEntry) -> T>(path: &Path, mut func: F) -> io::Resu
 
 /// Write a value that implements `Display` to a file
 pub fn write_value<V: Display>(path: &str, value: V) {
-    // eprintln!("writing {} to {}", value, path);
     let write_to_file = |path, value| -> io::Result<()> {
         let mut file = File::create(path)?;
         write!(file, "{}", value)?;
@@ -29,6 +41,6 @@ pub fn write_value<V: Display>(path: &str, value: V) {
     };
 
     if let Err(why) = write_to_file(path, value) {
-        eprintln!("failed to set value in {}: {}", path, why);
+        log::warn!("failed to set value in {}: {}", path, why);
     }
 }
@@ -2,8 +2,8 @@
 //
 // SPDX-License-Identifier: GPL-3.0-only
 
-use crate::{kernel_parameters::*, modprobe};
-use std::path::Path;
+use crate::{errors::WifiError, kernel_parameters::*, nl80211, plan::Plan, sysroot};
+use std::{fs, io};
 
 pub struct WifiDevice {
     device:      &'static str,
@@ -13,7 +13,7 @@ pub struct WifiDevice {
 
 impl WifiDevice {
     pub fn new(device: &'static str) -> Option<WifiDevice> {
-        if !Path::new(&["/sys/module/", device].concat()).exists() {
+        if !sysroot::path(["/sys/module/", device].concat()).exists() {
             return None;
         }
 
@@ -24,29 +24,44 @@ impl WifiDevice {
         })
     }
 
-    pub fn set(&self, power_level: u8) {
+    /// Sets the power level through module parameters, by reloading the driver. This
+    /// disconnects the interface, so it is only used where nl80211 power save is unsupported.
+    pub fn set(&self, plan: &mut Plan, power_level: u8) -> Result<(), WifiError> {
         if power_level > 5 {
             log::error!("invalid wifi power level. levels supported: 1-5");
-            return;
+            return Ok(());
         }
 
         if let (Some(ref save), Some(ref level)) = (self.power_save.get(), self.power_level.get()) {
             if power_level == 0 {
                 if save == "Y" {
-                    if let Err(why) = modprobe::reload(self.device, &["power_save=N"]) {
-                        log::error!("failed to reload {} module: {}", self.device, why);
-                    }
+                    self.reload(plan, &["power_save=N"])?;
                 }
             } else {
                 let power_level = power_level.to_string();
                 if save != "Y" || (save == "N" && level != &power_level) {
                     let options = &["power_save=Y", &format!("power_level={}", power_level)];
-                    if let Err(why) = modprobe::reload(self.device, options) {
-                        log::error!("failed to reload {} module: {}", self.device, why);
-                    }
+                    self.reload(plan, options)?;
                 }
             }
         }
+
+        Ok(())
+    }
+
+    fn reload(&self, plan: &mut Plan, options: &[&str]) -> Result<(), WifiError> {
+        let mut load = vec![self.device];
+        load.extend_from_slice(options);
+
+        for args in &[&["-r", self.device][..], &load] {
+            let status =
+                plan.run("modprobe", args).map_err(|why| WifiError::Reload(self.device, why))?;
+            if !status.success() {
+                return Err(WifiError::ReloadExitStatus(self.device, status));
+            }
+        }
+
+        Ok(())
     }
 }
 
@@ -57,3 +72,100 @@ impl DeviceList<WifiDevice> for WifiDevice {
         Box::new(Self::SUPPORTED.iter().flat_map(|dev| WifiDevice::new(dev)))
     }
 }
+
+/// Names of the wireless network interfaces.
+pub fn interfaces() -> io::Result<Vec<String>> {
+    let entries = match fs::read_dir(sysroot::path("/sys/class/net")) {
+        Ok(entries) => entries,
+        Err(why) if why.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(why) => return Err(why),
+    };
+
+    let mut interfaces = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if entry.path().join("wireless").exists() {
+            if let Ok(name) = entry.file_name().into_string() {
+                interfaces.push(name);
+            }
+        }
+    }
+
+    interfaces.sort();
+    Ok(interfaces)
+}
+
+/// The kernel module which drives an interface.
+fn module(interface: &str) -> Option<String> {
+    let path = sysroot::path(["/sys/class/net/", interface, "/device/driver/module"].concat());
+    fs::read_link(path).ok()?.file_name()?.to_str().map(String::from)
+}
+
+/// Whether an interface is up. An interface whose flags cannot be read is assumed to be up.
+fn is_up(interface: &str) -> bool {
+    const IFF_UP: u32 = 0x1;
+
+    fs::read_to_string(sysroot::path(["/sys/class/net/", interface, "/flags"].concat()))
+        .ok()
+        .and_then(|flags| u32::from_str_radix(flags.trim().trim_start_matches("0x"), 16).ok())
+        .map_or(true, |flags| flags & IFF_UP != 0)
+}
+
+/// The module parameter fallback for the driver of an interface, if it has one.
+fn fallback_device(interface: &str) -> Option<WifiDevice> {
+    let module = module(interface)?;
+    let device = WifiDevice::SUPPORTED.iter().find(|&&supported| supported == module)?;
+    WifiDevice::new(device)
+}
+
+/// Enables or disables power saving on every wireless interface, through nl80211. Interfaces
+/// where nl80211 power save is unsupported fall back to module parameters, using `power_level`
+/// when enabling, but only while none of the interfaces of the driver are up, as reloading it
+/// drops their connections.
+pub fn set_power_save(plan: &mut Plan, enabled: bool, power_level: u8) -> Result<(), WifiError> {
+    let interfaces = interfaces().map_err(WifiError::Interfaces)?;
+    let mut result = Ok(());
+
+    for interface in &interfaces {
+        let why = match plan.set_wifi_power_save(interface, enabled) {
+            Ok(()) => continue,
+            Err(why) => why,
+        };
+
+        let fallback = match fallback_device(interface) {
+            Some(device) if nl80211::is_unsupported(&why) => {
+                let up = interfaces
+                    .iter()
+                    .find(|other| module(other).as_deref() == Some(device.device) && is_up(other));
+
+                match up {
+                    Some(up) => {
+                        log::warn!(
+                            "{}: nl80211 power save is unsupported, and {} is not reloaded while \
+                             {} is up",
+                            interface,
+                            device.device,
+                            up
+                        );
+                        Err(WifiError::PowerSave(interface.clone(), why))
+                    }
+                    None => {
+                        log::warn!(
+                            "{}: nl80211 power save is unsupported; reloading {} instead",
+                            interface,
+                            device.device
+                        );
+                        device.set(plan, if enabled { power_level } else { 0 })
+                    }
+                }
+            }
+            _ => Err(WifiError::PowerSave(interface.clone(), why)),
+        };
+
+        if fallback.is_err() {
+            result = fallback;
+        }
+    }
+
+    result
+}